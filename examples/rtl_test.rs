@@ -1,4 +1,4 @@
-use rtlsdr_rs::{error::Result, RtlSdr};
+use seify_rtlsdr::{error::Result, RtlSdr};
 use std::sync::atomic::{AtomicBool, Ordering};
 
 const DEFAULT_BUF_LENGTH: usize = 16 * 16384;
@@ -6,14 +6,15 @@ const DEFAULT_BUF_LENGTH: usize = 16 * 16384;
 const SAMPLE_RATE: u32 = 2_048_000;
 
 fn main() -> Result<()> {
-    let devices = rtlsdr_rs::enumerate()?;
+    let devices = seify_rtlsdr::enumerate()?;
     println!("devices: {devices:?}");
 
     // Create shutdown flag and set it when ctrl-c signal caught
-    static shutdown: AtomicBool = AtomicBool::new(false);
+    static SHUTDOWN: AtomicBool = AtomicBool::new(false);
     ctrlc::set_handler(|| {
-        shutdown.swap(true, Ordering::Relaxed);
-    });
+        SHUTDOWN.swap(true, Ordering::Relaxed);
+    })
+    .unwrap();
 
     // Open device
     let mut sdr = RtlSdr::open(0).expect("Unable to open SDR device!");
@@ -44,18 +45,16 @@ fn main() -> Result<()> {
     println!("Reading samples in sync mode...");
     let mut buf: [u8; DEFAULT_BUF_LENGTH] = [0; DEFAULT_BUF_LENGTH];
     loop {
-        if shutdown.load(Ordering::Relaxed) {
+        if SHUTDOWN.load(Ordering::Relaxed) {
             break;
         }
-        let n = sdr.read_sync(&mut buf);
-        if n.is_err() {
-            println!("Read error: {n:#?}");
-        } else {
-            let n = n.unwrap();
-            if n < DEFAULT_BUF_LENGTH {
+        match sdr.read_sync(&mut buf) {
+            Ok(n) if n < DEFAULT_BUF_LENGTH => {
                 println!("Short read ({n:#?}), samples lost, exiting!");
                 break;
             }
+            Ok(_) => (),
+            Err(e) => println!("Read error: {e:#?}"),
         }
         // println!("read {} samples!", n.unwrap());
     }
@@ -0,0 +1,176 @@
+//! rtl_tcp compatible server, taking the same options as librtlsdr's rtl_tcp
+use std::net::TcpListener;
+use std::process;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use seify_rtlsdr::rtl_tcp::{self, ServerConfig};
+use seify_rtlsdr::{DeviceSelector, RtlSdr, TunerGain};
+
+const USAGE: &str = "rtl_tcp, an I/Q spectrum server for RTL2832 based DVB-T receivers
+
+Usage: rtl_tcp [options]
+    -a listen address (default: 127.0.0.1)
+    -p listen port (default: 1234)
+    -f frequency to tune to [Hz]
+    -g gain in dB (default: 0 for auto)
+    -s samplerate in Hz (default: 2048000 Hz)
+    -n max number of linked list buffers to keep (default: 500)
+    -d device index, serial, or 'virtual' for an emulated device (default: 0)
+    -P ppm error (default: 0)
+    -T enable bias-T on GPIO PIN 0 (works for rtl-sdr.com v3 dongles)
+    -v verbose logging";
+
+struct Options {
+    address: String,
+    port: u16,
+    freq: Option<u32>,
+    gain: f64,
+    rate: u32,
+    max_queued: usize,
+    device: DeviceSelector,
+    ppm: f64,
+    bias_tee: bool,
+    verbose: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        address: "127.0.0.1".to_string(),
+        port: 1234,
+        freq: None,
+        gain: 0.0,
+        rate: 2_048_000,
+        max_queued: rtl_tcp::DEFAULT_MAX_QUEUED,
+        device: DeviceSelector::Index(0),
+        ppm: 0.0,
+        bias_tee: false,
+        verbose: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-T" => opts.bias_tee = true,
+            "-v" => opts.verbose = true,
+            "-h" | "--help" => return Err(String::new()),
+            flag => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {flag}"))?;
+                let invalid = || format!("Invalid value for {flag}: {value}");
+                match flag {
+                    "-a" => opts.address = value.clone(),
+                    "-p" => opts.port = value.parse().map_err(|_| invalid())?,
+                    "-f" => opts.freq = Some(parse_hz(&value).ok_or_else(invalid)?),
+                    "-g" => opts.gain = value.parse().map_err(|_| invalid())?,
+                    "-s" => opts.rate = parse_hz(&value).ok_or_else(invalid)?,
+                    "-n" => opts.max_queued = value.parse().map_err(|_| invalid())?,
+                    "-d" => opts.device = parse_device(&value),
+                    "-P" => opts.ppm = value.parse().map_err(|_| invalid())?,
+                    _ => return Err(format!("Unknown option {flag}")),
+                }
+            }
+        }
+    }
+    Ok(opts)
+}
+
+/// Accepts plain numbers or k/M/G suffixes, e.g. 2.4M
+fn parse_hz(s: &str) -> Option<u32> {
+    let (num, scale) = match s.chars().last()? {
+        'k' | 'K' => (&s[..s.len() - 1], 1e3),
+        'm' | 'M' => (&s[..s.len() - 1], 1e6),
+        'g' | 'G' => (&s[..s.len() - 1], 1e9),
+        _ => (s, 1.0),
+    };
+    let hz = num.parse::<f64>().ok()? * scale;
+    (0.0..=u32::MAX as f64).contains(&hz).then_some(hz as u32)
+}
+
+fn parse_device(s: &str) -> DeviceSelector {
+    if s == "virtual" {
+        return DeviceSelector::Virtual(Box::default());
+    }
+    match s.parse() {
+        Ok(index) => DeviceSelector::Index(index),
+        Err(_) => DeviceSelector::Serial(s.to_string()),
+    }
+}
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn run(opts: Options) -> seify_rtlsdr::error::Result<()> {
+    let sdr = RtlSdr::open(opts.device)?;
+    sdr.set_sample_rate(opts.rate)?;
+    eprintln!("Tuner type: {:?}", sdr.get_tuner_type());
+    if let Some(freq) = opts.freq {
+        sdr.set_center_freq(freq)?;
+        eprintln!("Tuned to {freq} Hz");
+    }
+    if opts.ppm != 0.0 {
+        sdr.set_freq_correction_ppm(opts.ppm)?;
+    }
+    if opts.gain == 0.0 {
+        sdr.set_tuner_gain(TunerGain::Auto)?;
+        eprintln!("Tuner gain set to automatic");
+    } else {
+        let gain = (opts.gain * 10.0).round() as i32;
+        sdr.set_tuner_gain(TunerGain::Manual(gain))?;
+        eprintln!("Tuner gain set to {} dB", opts.gain);
+    }
+    sdr.set_bias_tee(opts.bias_tee)?;
+
+    let listener = TcpListener::bind((opts.address.as_str(), opts.port)).map_err(|source| {
+        seify_rtlsdr::error::RtlsdrError::Listen {
+            addr: format!("{}:{}", opts.address, opts.port),
+            source,
+        }
+    })?;
+    eprintln!("Listening on {}:{}", opts.address, opts.port);
+    let config = ServerConfig {
+        max_queued: opts.max_queued,
+        ..Default::default()
+    };
+    rtl_tcp::serve(&sdr, &listener, &config)
+}
+
+fn main() {
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("{msg}\n");
+            }
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+    };
+    log::set_logger(&StderrLogger).ok();
+    log::set_max_level(if opts.verbose {
+        LevelFilter::Info
+    } else {
+        LevelFilter::Warn
+    });
+    if let Err(e) = run(opts) {
+        eprintln!("{e}");
+        let mut source = std::error::Error::source(&e);
+        while let Some(e) = source {
+            eprintln!("  caused by: {e}");
+            source = e.source();
+        }
+        process::exit(1);
+    }
+}
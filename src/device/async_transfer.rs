@@ -0,0 +1,257 @@
+//! Asynchronous bulk transfers on top of libusb (rtlsdr_read_async equivalent)
+//!
+//! rusb only wraps the synchronous libusb API, so this keeps a pool of raw libusb
+//! transfers in flight and pumps the libusb event loop until cancelled.
+use std::collections::VecDeque;
+use std::mem;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use rusb::ffi::{self, constants::*};
+use rusb::UsbContext;
+
+use crate::error::Result;
+
+/// How long to block in the libusb event loop before re-checking the cancel flag
+const EVENT_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub(super) struct Transfer {
+    pub(super) ptr: *mut ffi::libusb_transfer,
+    pub(super) buf: Vec<u8>,
+    // Boxed so the address handed to libusb as user_data stays put
+    pub(super) done: Box<AtomicBool>,
+    pub(super) in_flight: bool,
+}
+
+/// The libusb calls that hand transfers back, separate so the release path can run
+/// without a device
+pub(super) trait TransferEvents {
+    fn cancel_transfer(&self, ptr: *mut ffi::libusb_transfer);
+    fn handle_events(&self) -> Result<()>;
+    fn free_transfer(&self, ptr: *mut ffi::libusb_transfer);
+}
+
+impl<T: UsbContext> TransferEvents for rusb::DeviceHandle<T> {
+    fn cancel_transfer(&self, ptr: *mut ffi::libusb_transfer) {
+        unsafe {
+            ffi::libusb_cancel_transfer(ptr);
+        }
+    }
+
+    fn handle_events(&self) -> Result<()> {
+        match self.context().handle_events(Some(EVENT_TIMEOUT)) {
+            Ok(()) | Err(rusb::Error::Interrupted) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn free_transfer(&self, ptr: *mut ffi::libusb_transfer) {
+        unsafe { ffi::libusb_free_transfer(ptr) };
+    }
+}
+
+/// Set of allocated transfers. Dropping the pool cancels anything still in flight
+/// and waits for libusb to hand the transfers back before freeing them.
+struct TransferPool<'a, T: UsbContext> {
+    handle: &'a rusb::DeviceHandle<T>,
+    transfers: Vec<Transfer>,
+}
+
+extern "system" fn transfer_callback(transfer: *mut ffi::libusb_transfer) {
+    // Only flag completion here, buffers are handed to the user from the event loop
+    // so user code never runs on the libusb callback path
+    unsafe {
+        let done = (*transfer).user_data as *const AtomicBool;
+        (*done).store(true, Ordering::Release);
+    }
+}
+
+impl<'a, T: UsbContext> TransferPool<'a, T> {
+    fn new(
+        handle: &'a rusb::DeviceHandle<T>,
+        endpoint: u8,
+        buf_num: usize,
+        buf_len: usize,
+    ) -> Result<Self> {
+        let mut pool = TransferPool {
+            handle,
+            transfers: Vec::with_capacity(buf_num),
+        };
+        for _ in 0..buf_num {
+            let ptr = unsafe { ffi::libusb_alloc_transfer(0) };
+            if ptr.is_null() {
+                return Err(rusb::Error::NoMem.into());
+            }
+            let mut transfer = Transfer {
+                ptr,
+                buf: vec![0; buf_len],
+                done: Box::new(AtomicBool::new(false)),
+                in_flight: false,
+            };
+            unsafe {
+                ffi::libusb_fill_bulk_transfer(
+                    ptr,
+                    handle.as_raw(),
+                    endpoint,
+                    transfer.buf.as_mut_ptr(),
+                    buf_len as c_int,
+                    transfer_callback,
+                    &*transfer.done as *const AtomicBool as *mut _,
+                    0, // no timeout, same as the sync bulk read
+                );
+            }
+            pool.transfers.push(transfer);
+        }
+        Ok(pool)
+    }
+
+    fn submit(&mut self, idx: usize) -> Result<()> {
+        let transfer = &mut self.transfers[idx];
+        transfer.done.store(false, Ordering::Release);
+        match unsafe { ffi::libusb_submit_transfer(transfer.ptr) } {
+            0 => {
+                transfer.in_flight = true;
+                Ok(())
+            }
+            err => {
+                error!("Failed to submit transfer {}: {}", idx, err);
+                Err(libusb_error(err).into())
+            }
+        }
+    }
+
+    fn is_done(&self, idx: usize) -> bool {
+        self.transfers[idx].done.load(Ordering::Acquire)
+    }
+
+    /// Mark a completed transfer as returned and get its status and received data
+    fn take(&mut self, idx: usize) -> (c_int, &[u8]) {
+        let transfer = &mut self.transfers[idx];
+        transfer.in_flight = false;
+        let (status, len) = unsafe { ((*transfer.ptr).status, (*transfer.ptr).actual_length) };
+        (status, &transfer.buf[..len.max(0) as usize])
+    }
+
+    fn handle_events(&self) -> Result<()> {
+        TransferEvents::handle_events(self.handle)
+    }
+}
+
+impl<'a, T: UsbContext> Drop for TransferPool<'a, T> {
+    fn drop(&mut self) {
+        release_transfers(self.handle, mem::take(&mut self.transfers));
+    }
+}
+
+/// Cancel in-flight transfers, wait for libusb to hand them back and free them. Transfers
+/// libusb still owns when the event loop fails are leaked along with their buffer and
+/// completion flag, since libusb may still write to both. Returns how many were leaked.
+pub(super) fn release_transfers(events: &dyn TransferEvents, transfers: Vec<Transfer>) -> usize {
+    for transfer in transfers.iter().filter(|t| t.in_flight) {
+        events.cancel_transfer(transfer.ptr);
+    }
+    // libusb still owns in-flight transfers until their callback runs
+    while transfers
+        .iter()
+        .any(|t| t.in_flight && !t.done.load(Ordering::Acquire))
+    {
+        if let Err(e) = events.handle_events() {
+            error!("Error while cancelling transfers: {}", e);
+            break;
+        }
+    }
+    let mut leaked = 0;
+    for transfer in transfers {
+        if !transfer.in_flight || transfer.done.load(Ordering::Acquire) {
+            events.free_transfer(transfer.ptr);
+        } else {
+            mem::forget(transfer.buf);
+            Box::leak(transfer.done);
+            leaked += 1;
+        }
+    }
+    if leaked > 0 {
+        warn!("Leaked {} transfers libusb didn't hand back", leaked);
+    }
+    leaked
+}
+
+/// Keep `buf_num` bulk transfers of `buf_len` bytes queued on `endpoint` and hand each
+/// filled buffer to `cb`, in order, until `cancel` is set or a transfer fails.
+pub fn read_bulk_async<T: UsbContext>(
+    handle: &rusb::DeviceHandle<T>,
+    endpoint: u8,
+    buf_num: usize,
+    buf_len: usize,
+    cancel: &AtomicBool,
+    cb: &mut dyn FnMut(&[u8]),
+) -> Result<()> {
+    let mut pool = TransferPool::new(handle, endpoint, buf_num, buf_len)?;
+    // Bulk transfers on one endpoint complete in submission order
+    let mut queue = VecDeque::with_capacity(buf_num);
+    for idx in 0..buf_num {
+        pool.submit(idx)?;
+        queue.push_back(idx);
+    }
+    info!(
+        "Started async read with {} buffers of {} bytes",
+        buf_num, buf_len
+    );
+
+    while !cancel.load(Ordering::Relaxed) {
+        pool.handle_events()?;
+        while let Some(&idx) = queue.front() {
+            if !pool.is_done(idx) || cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            queue.pop_front();
+            let (status, data) = pool.take(idx);
+            match status {
+                LIBUSB_TRANSFER_COMPLETED => cb(data),
+                LIBUSB_TRANSFER_TIMED_OUT => (),
+                _ => {
+                    error!("Async transfer failed with status {}", status);
+                    return Err(transfer_status_error(status).into());
+                }
+            }
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            pool.submit(idx)?;
+            queue.push_back(idx);
+        }
+    }
+    info!("Async read cancelled");
+    Ok(())
+}
+
+fn transfer_status_error(status: c_int) -> rusb::Error {
+    match status {
+        LIBUSB_TRANSFER_TIMED_OUT => rusb::Error::Timeout,
+        LIBUSB_TRANSFER_STALL => rusb::Error::Pipe,
+        LIBUSB_TRANSFER_NO_DEVICE => rusb::Error::NoDevice,
+        LIBUSB_TRANSFER_OVERFLOW => rusb::Error::Overflow,
+        LIBUSB_TRANSFER_CANCELLED => rusb::Error::Interrupted,
+        _ => rusb::Error::Io,
+    }
+}
+
+fn libusb_error(err: c_int) -> rusb::Error {
+    match err {
+        LIBUSB_ERROR_IO => rusb::Error::Io,
+        LIBUSB_ERROR_INVALID_PARAM => rusb::Error::InvalidParam,
+        LIBUSB_ERROR_ACCESS => rusb::Error::Access,
+        LIBUSB_ERROR_NO_DEVICE => rusb::Error::NoDevice,
+        LIBUSB_ERROR_NOT_FOUND => rusb::Error::NotFound,
+        LIBUSB_ERROR_BUSY => rusb::Error::Busy,
+        LIBUSB_ERROR_TIMEOUT => rusb::Error::Timeout,
+        LIBUSB_ERROR_OVERFLOW => rusb::Error::Overflow,
+        LIBUSB_ERROR_PIPE => rusb::Error::Pipe,
+        LIBUSB_ERROR_INTERRUPTED => rusb::Error::Interrupted,
+        LIBUSB_ERROR_NO_MEM => rusb::Error::NoMem,
+        LIBUSB_ERROR_NOT_SUPPORTED => rusb::Error::NotSupported,
+        _ => rusb::Error::Other,
+    }
+}
@@ -0,0 +1,56 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rusb::ffi;
+
+use super::async_transfer::{release_transfers, Transfer, TransferEvents};
+use crate::error::{Result, RtlsdrError};
+
+/// Event loop that fails straight away, without completing anything
+#[derive(Default)]
+struct FailingEvents {
+    cancelled: RefCell<Vec<usize>>,
+    freed: RefCell<Vec<usize>>,
+}
+
+impl TransferEvents for FailingEvents {
+    fn cancel_transfer(&self, ptr: *mut ffi::libusb_transfer) {
+        self.cancelled.borrow_mut().push(ptr as usize);
+    }
+    fn handle_events(&self) -> Result<()> {
+        Err(RtlsdrError::Usb(rusb::Error::Io))
+    }
+    fn free_transfer(&self, ptr: *mut ffi::libusb_transfer) {
+        self.freed.borrow_mut().push(ptr as usize);
+    }
+}
+
+fn transfer(id: usize, in_flight: bool, done: bool) -> Transfer {
+    Transfer {
+        // Never dereferenced by the fake event loop
+        ptr: id as *mut ffi::libusb_transfer,
+        buf: vec![0; 512],
+        done: Box::new(AtomicBool::new(done)),
+        in_flight,
+    }
+}
+
+#[test]
+fn test_release_leaks_transfers_still_owned_by_libusb() {
+    let events = FailingEvents::default();
+    let pending = transfer(3, true, false);
+    let done_flag: *const AtomicBool = &*pending.done;
+    let buf = pending.buf.as_ptr() as *mut u8;
+    let transfers = vec![transfer(1, false, false), transfer(2, true, true), pending];
+
+    assert_eq!(release_transfers(&events, transfers), 1);
+    assert_eq!(*events.cancelled.borrow(), vec![2, 3]);
+    assert_eq!(*events.freed.borrow(), vec![1, 2]);
+
+    // libusb completing the pending transfer late writes to memory that's still valid
+    unsafe {
+        *buf = 0xaa;
+        (*done_flag).store(true, Ordering::Release);
+        assert!((*done_flag).load(Ordering::Acquire));
+    }
+}
@@ -222,6 +222,7 @@ pub const KNOWN_DEVICES: &[UsbDeviceSignature; 42] = &[
 
 pub const EEPROM_ADDR: u16 = 0xa0;
 pub const EEPROM_SIZE: usize = 256;
+pub const EEPROM_WRITE_DELAY: Duration = Duration::from_millis(5);
 
 // Blocks
 pub const BLOCK_DEMOD: u16 = 0;
@@ -1,53 +1,94 @@
+use std::sync::atomic::AtomicBool;
 use std::time::Duration;
 
-use crate::error::Result;
-use crate::error::RtlsdrError::RtlsdrErr;
+use crate::error::{Result, RtlsdrError};
+use log::info;
 use rusb::{Context, UsbContext};
 
+use super::async_transfer;
+use super::Transport;
 use super::KNOWN_DEVICES;
+use crate::DeviceSelector;
 
 #[derive(Debug)]
 pub struct DeviceHandle {
     handle: rusb::DeviceHandle<Context>,
 }
 impl DeviceHandle {
-    pub fn open(index: usize) -> Result<Self> {
+    pub fn open(selector: &DeviceSelector) -> Result<Self> {
         let mut context = Context::new()?;
-        let handle = DeviceHandle::open_device(&mut context, index)?;
+        let handle = DeviceHandle::open_device(&mut context, selector)?;
         Ok(DeviceHandle { handle })
     }
 
     pub fn open_device<T: UsbContext>(
         context: &mut T,
-        mut index: usize,
+        selector: &DeviceSelector,
     ) -> Result<rusb::DeviceHandle<T>> {
-        let devices = context.devices()?;
-
-        for found in devices.iter() {
-            let device_desc = found.device_descriptor()?;
-            for dev in KNOWN_DEVICES.iter() {
-                if device_desc.vendor_id() == dev.vid && device_desc.product_id() == dev.pid {
-                    if index == 0 {
-                        let mut dev = found.open()?;
-                        dev.set_auto_detach_kernel_driver(true).ok();
-                        return Ok(dev);
-                    } else {
-                        index -= 1;
+        let mut found = match selector {
+            DeviceSelector::Index(index) => known_devices(context)?
+                .into_iter()
+                .skip(*index)
+                .take(1)
+                .collect(),
+            DeviceSelector::Serial(serial) => known_devices(context)?
+                .into_iter()
+                .filter(|dev| read_serial(dev).as_deref() == Some(serial.as_str()))
+                .collect(),
+            DeviceSelector::BusAddress { bus, address } => known_devices(context)?
+                .into_iter()
+                .filter(|dev| dev.bus_number() == *bus && dev.address() == *address)
+                .collect(),
+            DeviceSelector::PortPath { bus, ports } => known_devices(context)?
+                .into_iter()
+                .filter(|dev| {
+                    dev.bus_number() == *bus && dev.port_numbers().ok().as_ref() == Some(ports)
+                })
+                .collect(),
+            // Match any device here, not just KNOWN_DEVICES, so dongles with a
+            // reprogrammed VID/PID can still be opened
+            DeviceSelector::VidPid { vid, pid } => {
+                let mut devs = Vec::new();
+                for dev in context.devices()?.iter() {
+                    let desc = dev.device_descriptor()?;
+                    if desc.vendor_id() == *vid && desc.product_id() == *pid {
+                        devs.push(dev);
                     }
                 }
+                devs
+            }
+            DeviceSelector::Virtual(_) => Vec::new(),
+        };
+        match found.len() {
+            0 => Err(RtlsdrError::DeviceNotFound(selector.to_string())),
+            1 => {
+                let dev = found.remove(0).open()?;
+                dev.set_auto_detach_kernel_driver(true).ok();
+                Ok(dev)
             }
+            count => Err(RtlsdrError::MultipleDevices {
+                selector: selector.to_string(),
+                count,
+            }),
         }
-        Err(RtlsdrErr("No device found".to_string()))
     }
+}
 
-    pub fn claim_interface(&mut self, iface: u8) -> Result<()> {
-        Ok(self.handle.claim_interface(iface)?)
+impl Transport for DeviceHandle {
+    fn claim_interface(&mut self, iface: u8) -> Result<()> {
+        match self.handle.claim_interface(iface) {
+            // Auto-detach failed, e.g. for lack of permission
+            Err(rusb::Error::Busy) if self.handle.kernel_driver_active(iface).unwrap_or(false) => {
+                Err(RtlsdrError::KernelDriverActive)
+            }
+            r => Ok(r?),
+        }
     }
-    pub fn reset(&mut self) -> Result<()> {
+    fn reset(&mut self) -> Result<()> {
         Ok(self.handle.reset()?)
     }
 
-    pub fn read_control(
+    fn read_control(
         &self,
         request_type: u8,
         request: u8,
@@ -61,7 +102,7 @@ impl DeviceHandle {
             .read_control(request_type, request, value, index, buf, timeout)?)
     }
 
-    pub fn write_control(
+    fn write_control(
         &self,
         request_type: u8,
         request: u8,
@@ -75,7 +116,49 @@ impl DeviceHandle {
             .write_control(request_type, request, value, index, buf, timeout)?)
     }
 
-    pub fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize> {
         Ok(self.handle.read_bulk(endpoint, buf, timeout)?)
     }
+
+    fn read_bulk_async(
+        &self,
+        endpoint: u8,
+        buf_num: usize,
+        buf_len: usize,
+        cancel: &AtomicBool,
+        cb: &mut dyn FnMut(&[u8]),
+    ) -> Result<()> {
+        async_transfer::read_bulk_async(&self.handle, endpoint, buf_num, buf_len, cancel, cb)
+    }
+}
+
+/// Supported devices in enumeration order, which is what device indexes refer to
+pub fn known_devices<T: UsbContext>(context: &T) -> Result<Vec<rusb::Device<T>>> {
+    let mut devs = Vec::new();
+    for found in context.devices()?.iter() {
+        let device_desc = found.device_descriptor()?;
+        if KNOWN_DEVICES
+            .iter()
+            .any(|dev| device_desc.vendor_id() == dev.vid && device_desc.product_id() == dev.pid)
+        {
+            devs.push(found);
+        }
+    }
+    Ok(devs)
+}
+
+fn read_serial<T: UsbContext>(dev: &rusb::Device<T>) -> Option<String> {
+    let desc = dev.device_descriptor().ok()?;
+    match dev.open() {
+        Ok(handle) => handle.read_serial_number_string_ascii(&desc).ok(),
+        Err(e) => {
+            info!(
+                "Unable to open device on bus {} address {} to read serial: {}",
+                dev.bus_number(),
+                dev.address(),
+                e
+            );
+            None
+        }
+    }
 }
@@ -1,9 +1,43 @@
+use std::time::Duration;
+
+use mockall::mock;
 use mockall::predicate::{self, eq};
 
-use crate::device::{Device, EEPROM_SIZE};
+use crate::device::{Device, Transport, EEPROM_SIZE};
+use crate::error::{EepromError, RegisterAddr, RegisterBlock, Result, RtlsdrError};
 
 use super::{BLOCK_SYS, CTRL_IN, CTRL_OUT, CTRL_TIMEOUT, GPO};
 
+// Written out by hand since automock can't handle read_bulk_async's callback argument,
+// which keeps its default implementation here
+mock! {
+    #[derive(Debug)]
+    pub Transport {}
+    impl Transport for Transport {
+        fn claim_interface(&mut self, iface: u8) -> Result<()>;
+        fn reset(&mut self) -> Result<()>;
+        fn read_control(
+            &self,
+            request_type: u8,
+            request: u8,
+            value: u16,
+            index: u16,
+            buf: &mut [u8],
+            timeout: Duration,
+        ) -> Result<usize>;
+        fn write_control(
+            &self,
+            request_type: u8,
+            request: u8,
+            value: u16,
+            index: u16,
+            buf: &[u8],
+            timeout: Duration,
+        ) -> Result<usize>;
+        fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize>;
+    }
+}
+
 #[test]
 fn test_read_reg_u8() {
     let block = BLOCK_SYS;
@@ -11,7 +45,7 @@ fn test_read_reg_u8() {
     let addr = GPO;
     let data_expected = 0x12_u16;
 
-    let mut mock_handle = MockDeviceHandle::new();
+    let mut mock_handle = MockTransport::new();
     mock_handle
         .expect_read_control()
         .times(1)
@@ -29,7 +63,7 @@ fn test_read_reg_u8() {
             Ok(1)
         });
     let device = Device {
-        handle: mock_handle,
+        handle: Box::new(mock_handle),
     };
     let result = device.read_reg(block, addr, 1).unwrap();
     assert_eq!(data_expected, result);
@@ -40,9 +74,10 @@ fn test_read_reg_u16() {
     let block = BLOCK_SYS;
     let index_expected = BLOCK_SYS << 8;
     let addr = GPO;
-    let data_expected = u16::to_be_bytes(0x123);
+    // Registers are read little endian
+    let data_expected = u16::to_le_bytes(0x123);
 
-    let mut mock_handle = MockDeviceHandle::new();
+    let mut mock_handle = MockTransport::new();
     mock_handle
         .expect_read_control()
         .times(1)
@@ -60,10 +95,10 @@ fn test_read_reg_u16() {
             Ok(2)
         });
     let device = Device {
-        handle: mock_handle,
+        handle: Box::new(mock_handle),
     };
     let result = device.read_reg(block, addr, 2).unwrap();
-    assert_eq!(u16::from_be_bytes(data_expected), result);
+    assert_eq!(0x123, result);
 }
 
 #[test]
@@ -73,7 +108,7 @@ fn test_write_reg_u8() {
     let addr = GPO;
     let data_expected = 0xef_u16;
 
-    let mut mock_handle = MockDeviceHandle::new();
+    let mut mock_handle = MockTransport::new();
     mock_handle
         .expect_write_control()
         .times(1)
@@ -91,7 +126,7 @@ fn test_write_reg_u8() {
             Ok(1)
         });
     let device = Device {
-        handle: mock_handle,
+        handle: Box::new(mock_handle),
     };
     let result = device.write_reg(block, addr, data_expected, 1).unwrap();
     assert_eq!(1, result);
@@ -104,7 +139,7 @@ fn test_write_reg_u16() {
     let addr = GPO;
     let data_expected = 0xefab_u16;
 
-    let mut mock_handle = MockDeviceHandle::new();
+    let mut mock_handle = MockTransport::new();
     mock_handle
         .expect_write_control()
         .times(1)
@@ -122,7 +157,7 @@ fn test_write_reg_u16() {
             Ok(1)
         });
     let device = Device {
-        handle: mock_handle,
+        handle: Box::new(mock_handle),
     };
     let result = device.write_reg(block, addr, data_expected, 2).unwrap();
     assert_eq!(1, result);
@@ -134,7 +169,7 @@ fn test_demod_read_reg() {
     let addr = 0x1_u16;
     let value = 0x12;
 
-    let mut mock_handle = MockDeviceHandle::new();
+    let mut mock_handle = MockTransport::new();
     mock_handle
         .expect_read_control()
         .times(1)
@@ -151,20 +186,105 @@ fn test_demod_read_reg() {
             Ok(2)
         });
     let device = Device {
-        handle: mock_handle,
+        handle: Box::new(mock_handle),
     };
     let result = device.demod_read_reg(page, addr).unwrap();
     assert_eq!(value as u16, result);
 }
 
 #[test]
-#[should_panic]
 fn test_read_eeprom_out_of_range() {
-    let mock_handle = MockDeviceHandle::new();
+    let mock_handle = MockTransport::new();
+    let device = Device {
+        handle: Box::new(mock_handle),
+    };
+    let mut data = [0; 5];
+    // Try to read more than eeprom size
+    assert!(matches!(
+        device.read_eeprom(&mut data, 0, EEPROM_SIZE),
+        Err(RtlsdrError::Eeprom(EepromError::OutOfRange { .. }))
+    ));
+}
+
+#[test]
+fn test_register_error_context() {
+    let mut mock_handle = MockTransport::new();
+    mock_handle
+        .expect_read_control()
+        .times(1)
+        .returning(|_, _, _, _, _, _| Err(rusb::Error::Pipe.into()));
+    mock_handle
+        .expect_write_control()
+        .times(1)
+        .returning(|_, _, _, _, _, _| Err(rusb::Error::NoDevice.into()));
+    let device = Device {
+        handle: Box::new(mock_handle),
+    };
+    assert!(matches!(
+        device.read_reg(BLOCK_SYS, GPO, 1),
+        Err(RtlsdrError::Register {
+            block: RegisterBlock::Sys,
+            addr: GPO,
+            source: rusb::Error::Pipe,
+        })
+    ));
+    // Disconnects stay recognisable whichever register they hit
+    assert!(matches!(
+        device.write_reg(BLOCK_SYS, GPO, 0, 1),
+        Err(RtlsdrError::DeviceDisconnected)
+    ));
+}
+
+#[test]
+fn test_demod_register_errors() {
+    let mut mock_handle = MockTransport::new();
+    mock_handle
+        .expect_read_control()
+        .times(1)
+        .returning(|_, _, _, _, _, _| Err(rusb::Error::Timeout.into()));
+    mock_handle
+        .expect_write_control()
+        .times(1)
+        .returning(|_, _, _, _, _, _| Err(rusb::Error::Pipe.into()));
+    let device = Device {
+        handle: Box::new(mock_handle),
+    };
+    // Reach the caller with the page and address rather than being logged and dropped
+    assert!(matches!(
+        device.demod_read_reg(1, 0x15),
+        Err(RtlsdrError::Timeout {
+            register: Some(RegisterAddr {
+                block: RegisterBlock::Demod { page: Some(1) },
+                addr: 0x15,
+            }),
+        })
+    ));
+    assert!(matches!(
+        device.demod_write_reg(0, 0x19, 0x25, 1),
+        Err(RtlsdrError::Register {
+            block: RegisterBlock::Demod { page: Some(0) },
+            addr: 0x19,
+            source: rusb::Error::Pipe,
+        })
+    ));
+}
+
+#[test]
+fn test_invalid_register_width() {
+    // Rejected before anything reaches the transport
     let device = Device {
-        handle: mock_handle,
+        handle: Box::new(MockTransport::new()),
     };
-    let data = [0; 5];
-    // Try to read more than eeprom size - should panic
-    device.read_eeprom(&data, 0, EEPROM_SIZE).unwrap();
+    assert!(matches!(
+        device.read_reg(BLOCK_SYS, GPO, 4),
+        Err(RtlsdrError::InvalidRegisterWidth(4))
+    ));
+    assert!(matches!(
+        device.write_reg(BLOCK_SYS, GPO, 0, 0),
+        Err(RtlsdrError::InvalidRegisterWidth(0))
+    ));
+    assert!(matches!(
+        device.demod_write_reg(1, 0x01, 0, 3),
+        Err(RtlsdrError::InvalidRegisterWidth(3))
+    ));
 }
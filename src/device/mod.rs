@@ -1,24 +1,41 @@
+mod async_transfer;
+#[cfg(test)]
+mod async_transfer_test;
 pub mod constants;
 pub use constants::*;
 pub mod device_handle;
+#[cfg(test)]
+mod device_test;
+pub mod transport;
 use device_handle::DeviceHandle;
+pub use transport::Transport;
 
-use crate::error::Result;
+use crate::error::{EepromError, RegisterBlock, Result, RtlsdrError};
+use crate::virtual_device::VirtualDevice;
+use crate::DeviceSelector;
 use byteorder::{ByteOrder, LittleEndian};
 /// Low-level io functions for interfacing with rusb(libusb)
-use log::{error, info};
+use log::info;
+use std::sync::atomic::AtomicBool;
+use std::thread;
 use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Device {
-    handle: DeviceHandle,
+    handle: Box<dyn Transport>,
 }
 
 impl Device {
-    pub fn new(index: usize) -> Result<Device> {
-        Ok(Device {
-            handle: DeviceHandle::open(index)?,
-        })
+    pub fn new(selector: &DeviceSelector) -> Result<Device> {
+        let handle: Box<dyn Transport> = match selector {
+            DeviceSelector::Virtual(config) => Box::new(VirtualDevice::new((**config).clone())),
+            _ => Box::new(DeviceHandle::open(selector)?),
+        };
+        Ok(Device { handle })
+    }
+
+    pub fn from_transport(handle: Box<dyn Transport>) -> Device {
+        Device { handle }
     }
 
     pub fn claim_interface(&mut self, iface: u8) -> Result<()> {
@@ -41,19 +58,19 @@ impl Device {
         Ok(())
     }
 
-    /// TODO: This only supports len of 1 or 2, maybe use an enum or make this generic?
     pub fn read_reg(&self, block: u16, addr: u16, len: usize) -> Result<u16> {
-        assert!(len == 1 || len == 2);
+        check_reg_width(len)?;
         let mut data: [u8; 2] = [0, 0];
         let index: u16 = block << 8;
         self.handle
-            .read_control(CTRL_IN, 0, addr, index, &mut data[..len], CTRL_TIMEOUT)?;
+            .read_control(CTRL_IN, 0, addr, index, &mut data[..len], CTRL_TIMEOUT)
+            .map_err(|e| e.at_register(register_block(block), addr))?;
         // Read registers as little endian, but write as big; not sure why
         Ok(LittleEndian::read_u16(&data))
     }
 
     pub fn write_reg(&self, block: u16, addr: u16, val: u16, len: usize) -> Result<usize> {
-        assert!(len == 1 || len == 2);
+        check_reg_width(len)?;
         // Read registers as little endian, but write as big; not sure why
         let data: [u8; 2] = val.to_be_bytes();
         let data_slice = if len == 1 { &data[1..2] } else { &data };
@@ -61,58 +78,44 @@ impl Device {
         // info!("write_reg addr: {:x} index: {:x} data: {:x?} data slice: {}", addr, index, data, data_slice.len());
         self.handle
             .write_control(CTRL_OUT, 0, addr, index, data_slice, CTRL_TIMEOUT)
+            .map_err(|e| e.at_register(register_block(block), addr))
     }
 
     /// Only supports u8 reads
     pub fn demod_read_reg(&self, page: u16, addr: u16) -> Result<u16> {
         let mut data = [0_u8];
         let index = page;
-        let _bytes = match self.handle.read_control(
-            CTRL_IN,
-            0,
-            (addr << 8) | 0x20,
-            index,
-            &mut data,
-            CTRL_TIMEOUT,
-        ) {
-            Ok(n) => {
-                // info!("demod_read_reg got {} bytes: [{:#02x}, {:#02x}] value: {:x}", n, data[0], data[1], BigEndian::read_u16(&data));
-                Ok(n)
-            }
-            Err(e) => {
-                error!(
-                    "demod_read_reg failed: {} page: {:#02x} addr: {:#02x}",
-                    e, page, addr
-                );
-                Err(e)
-            }
-        };
+        self.handle
+            .read_control(
+                CTRL_IN,
+                0,
+                (addr << 8) | 0x20,
+                index,
+                &mut data,
+                CTRL_TIMEOUT,
+            )
+            .map_err(|e| e.at_register(RegisterBlock::Demod { page: Some(page) }, addr))?;
         let reg: u16 = data[0] as u16;
         Ok(reg)
     }
 
-    /// TODO: only supports len of 1 or 2, maybe use enum or make this generic
-    pub fn demod_write_reg(&self, page: u16, mut addr: u16, val: u16, len: usize) -> Result<usize> {
-        assert!(len == 1 || len == 2);
+    pub fn demod_write_reg(&self, page: u16, addr: u16, val: u16, len: usize) -> Result<usize> {
+        check_reg_width(len)?;
         let index = 0x10 | page;
-        addr = (addr << 8) | 0x20;
         let data: [u8; 2] = val.to_be_bytes();
         let data_slice = if len == 1 { &data[1..2] } else { &data };
 
-        let bytes =
-            match self
-                .handle
-                .write_control(CTRL_OUT, 0, addr, index, data_slice, CTRL_TIMEOUT)
-            {
-                Ok(n) => n,
-                Err(e) => {
-                    error!(
-                        "demod_write_reg failed: {} page: {:#02x} addr: {:#02x} val: {:#02x}",
-                        e, page, addr, val
-                    );
-                    0
-                }
-            };
+        let bytes = self
+            .handle
+            .write_control(
+                CTRL_OUT,
+                0,
+                (addr << 8) | 0x20,
+                index,
+                data_slice,
+                CTRL_TIMEOUT,
+            )
+            .map_err(|e| e.at_register(RegisterBlock::Demod { page: Some(page) }, addr))?;
 
         self.demod_read_reg(0x0a, 0x1)?;
 
@@ -123,15 +126,76 @@ impl Device {
         self.handle.read_bulk(0x81, buf, Duration::ZERO)
     }
 
-    pub fn read_eeprom(&self, data: &[u8], offset: u8, len: usize) -> Result<usize> {
-        assert!((len + offset as usize) <= EEPROM_SIZE);
+    /// Stream from the bulk endpoint with `buf_num` transfers of `buf_len` bytes in flight
+    pub fn bulk_transfer_async(
+        &self,
+        buf_num: usize,
+        buf_len: usize,
+        cancel: &AtomicBool,
+        cb: &mut dyn FnMut(&[u8]),
+    ) -> Result<()> {
+        self.handle
+            .read_bulk_async(0x81, buf_num, buf_len, cancel, cb)
+    }
+
+    pub fn read_eeprom(&self, data: &mut [u8], offset: u8, len: usize) -> Result<usize> {
+        if len + offset as usize > EEPROM_SIZE || len > data.len() {
+            return Err(EepromError::OutOfRange {
+                offset: offset as usize,
+                len,
+            }
+            .into());
+        }
         self.write_array(BLOCK_IIC, EEPROM_ADDR, &[offset], 1)?;
-        for i in data.iter().take(len) {
-            self.read_array(BLOCK_IIC, EEPROM_ADDR, &mut [*i], 1)?;
+        for byte in data.iter_mut().take(len) {
+            self.read_array(BLOCK_IIC, EEPROM_ADDR, std::slice::from_mut(byte), 1)?;
+        }
+        Ok(len)
+    }
+
+    /// Write bytes to the EEPROM, skipping any that already hold the right value
+    pub fn write_eeprom(&self, data: &[u8], offset: u8, len: usize) -> Result<usize> {
+        if len + offset as usize > EEPROM_SIZE || len > data.len() {
+            return Err(EepromError::OutOfRange {
+                offset: offset as usize,
+                len,
+            }
+            .into());
+        }
+        for (i, byte) in data.iter().take(len).enumerate() {
+            let mut cmd: [u8; 2] = [offset + i as u8, 0];
+            self.write_array(BLOCK_IIC, EEPROM_ADDR, &cmd, 1)?;
+            self.read_array(BLOCK_IIC, EEPROM_ADDR, &mut cmd[1..], 1)?;
+            if cmd[1] == *byte {
+                continue;
+            }
+            cmd[1] = *byte;
+            if self.write_array(BLOCK_IIC, EEPROM_ADDR, &cmd, 2)? != cmd.len() {
+                return Err(EepromError::WriteFailed(cmd[0]).into());
+            }
+            // Some EEPROMs (e.g. ATC 240LC02) need a delay between writes or they fail
+            thread::sleep(EEPROM_WRITE_DELAY);
         }
         Ok(len)
     }
 
+    pub fn set_gpio_bit(&self, gpio: u8, val: bool) -> Result<()> {
+        let gpio = 1 << gpio;
+        let mut r = self.read_reg(BLOCK_SYS, GPO, 1)?;
+        r = if val { r | gpio } else { r & !gpio };
+        self.write_reg(BLOCK_SYS, GPO, r, 1)?;
+        Ok(())
+    }
+
+    pub fn set_gpio_output(&self, gpio: u8) -> Result<()> {
+        let gpio = 1 << gpio;
+        let mut r = self.read_reg(BLOCK_SYS, GPD, 1)?;
+        self.write_reg(BLOCK_SYS, GPD, r & !gpio, 1)?;
+        r = self.read_reg(BLOCK_SYS, GPOE, 1)?;
+        self.write_reg(BLOCK_SYS, GPOE, r | gpio, 1)?;
+        Ok(())
+    }
+
     pub fn i2c_read_reg(&self, i2c_addr: u8, reg: u8) -> Result<u8> {
         let addr: u16 = i2c_addr.into();
         let reg: [u8; 1] = [reg];
@@ -158,11 +222,34 @@ impl Device {
         let index: u16 = block << 8;
         self.handle
             .read_control(CTRL_IN, 0, addr, index, arr, CTRL_TIMEOUT)
+            .map_err(|e| e.at_register(register_block(block), addr))
     }
 
     pub fn write_array(&self, block: u16, addr: u16, arr: &[u8], len: usize) -> Result<usize> {
         let index: u16 = (block << 8) | 0x10;
         self.handle
             .write_control(CTRL_OUT, 0, addr, index, &arr[..len], CTRL_TIMEOUT)
+            .map_err(|e| e.at_register(register_block(block), addr))
+    }
+}
+
+/// Registers are accessed as either 1 or 2 bytes
+fn check_reg_width(len: usize) -> Result<()> {
+    match len {
+        1 | 2 => Ok(()),
+        len => Err(RtlsdrError::InvalidRegisterWidth(len)),
+    }
+}
+
+fn register_block(block: u16) -> RegisterBlock {
+    match block {
+        BLOCK_DEMOD => RegisterBlock::Demod { page: None },
+        BLOCK_USB => RegisterBlock::Usb,
+        BLOCK_SYS => RegisterBlock::Sys,
+        BLOCK_TUN => RegisterBlock::Tuner,
+        BLOCK_ROM => RegisterBlock::Rom,
+        BLOCK_IRB => RegisterBlock::Ir,
+        BLOCK_IIC => RegisterBlock::I2c,
+        block => RegisterBlock::Other(block),
     }
 }
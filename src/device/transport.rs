@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::error::Result;
+
+/// The USB operations the driver needs from a device. `DeviceHandle` implements this
+/// over libusb; mocks, recorders and emulators can implement it to run the driver
+/// without hardware.
+pub trait Transport: std::fmt::Debug + Send {
+    fn claim_interface(&mut self, iface: u8) -> Result<()>;
+    fn reset(&mut self) -> Result<()>;
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize>;
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize>;
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> Result<usize>;
+
+    /// Stream `buf_len` byte buffers from `endpoint` to `cb` until `cancel` is set.
+    /// The default implementation loops over `read_bulk`, so only one transfer is
+    /// ever in flight.
+    fn read_bulk_async(
+        &self,
+        endpoint: u8,
+        _buf_num: usize,
+        buf_len: usize,
+        cancel: &AtomicBool,
+        cb: &mut dyn FnMut(&[u8]),
+    ) -> Result<()> {
+        let mut buf = vec![0; buf_len];
+        while !cancel.load(Ordering::Relaxed) {
+            let n = self.read_bulk(endpoint, &mut buf, Duration::ZERO)?;
+            cb(&buf[..n]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Typed view of the RTL2832U configuration EEPROM, using the same layout as
+//! librtlsdr's rtl_eeprom tool.
+use crate::error::{EepromError, Result};
+
+pub use crate::device::EEPROM_SIZE;
+
+// Header bytes identifying an RTL2832 configuration
+const EEPROM_HEADER: [u8; 2] = [0x28, 0x32];
+const HAVE_SERIAL: u8 = 0xa5;
+// Flag bits in byte 7
+const FLAGS_BASE: u8 = 0x14;
+const FLAG_REMOTE_WAKEUP: u8 = 0x01;
+const FLAG_ENABLE_IR: u8 = 0x02;
+// String descriptors start here and must end before the IR config at STR_END
+const STR_OFFSET: usize = 0x09;
+const STR_END: usize = 78;
+const USB_DT_STRING: u8 = 0x03;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EepromConfig {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: String,
+    pub product: String,
+    pub serial: String,
+    /// Whether the device reports its serial number to the host
+    pub have_serial: bool,
+    /// IR endpoint enabled. The RTL-SDR Blog driver keeps the bias tee on when this is cleared.
+    pub enable_ir: bool,
+    /// Remote wakeup enabled. The RTL-SDR Blog driver forces direct sampling when this is set.
+    pub remote_wakeup: bool,
+}
+
+impl Default for EepromConfig {
+    /// The configuration rtl_eeprom writes for a generic dongle
+    fn default() -> Self {
+        EepromConfig {
+            vid: 0x0bda,
+            pid: 0x2838,
+            manufacturer: "Realtek".to_string(),
+            product: "RTL2838UHIDIR".to_string(),
+            serial: "00000001".to_string(),
+            have_serial: true,
+            enable_ir: true,
+            remote_wakeup: false,
+        }
+    }
+}
+
+impl EepromConfig {
+    /// Parse a configuration from an EEPROM image
+    pub fn parse(data: &[u8]) -> Result<EepromConfig> {
+        if data.len() <= STR_END {
+            return Err(EepromError::ImageTooShort(data.len()).into());
+        }
+        if data[0..2] != EEPROM_HEADER {
+            return Err(EepromError::InvalidHeader(data[0], data[1]).into());
+        }
+        let (manufacturer, pos) = get_string_descriptor(data, STR_OFFSET)?;
+        let (product, pos) = get_string_descriptor(data, pos)?;
+        let (serial, _) = get_string_descriptor(data, pos)?;
+        Ok(EepromConfig {
+            vid: u16::from_le_bytes([data[2], data[3]]),
+            pid: u16::from_le_bytes([data[4], data[5]]),
+            manufacturer,
+            product,
+            serial,
+            have_serial: data[6] == HAVE_SERIAL,
+            remote_wakeup: data[7] & FLAG_REMOTE_WAKEUP != 0,
+            enable_ir: data[7] & FLAG_ENABLE_IR != 0,
+        })
+    }
+
+    /// Write this configuration into an EEPROM image, leaving bytes past the
+    /// configuration area untouched
+    pub fn write_to(&self, data: &mut [u8]) -> Result<()> {
+        if data.len() < EEPROM_SIZE {
+            return Err(EepromError::ImageTooShort(data.len()).into());
+        }
+        data[0..2].copy_from_slice(&EEPROM_HEADER);
+        data[2..4].copy_from_slice(&self.vid.to_le_bytes());
+        data[4..6].copy_from_slice(&self.pid.to_le_bytes());
+        data[6] = if self.have_serial { HAVE_SERIAL } else { 0x00 };
+        data[7] = FLAGS_BASE;
+        if self.remote_wakeup {
+            data[7] |= FLAG_REMOTE_WAKEUP;
+        }
+        if self.enable_ir {
+            data[7] |= FLAG_ENABLE_IR;
+        }
+        data[8] = 0x02;
+        let pos = set_string_descriptor(data, STR_OFFSET, &self.manufacturer)?;
+        let pos = set_string_descriptor(data, pos, &self.product)?;
+        set_string_descriptor(data, pos, &self.serial)?;
+        // Length of IR config
+        data[STR_END] = 0x00;
+        Ok(())
+    }
+}
+
+/// Read a USB string descriptor at `pos`, returning the string and the position after it
+fn get_string_descriptor(data: &[u8], pos: usize) -> Result<(String, usize)> {
+    let len = data[pos] as usize;
+    if data[pos + 1] != USB_DT_STRING || len < 2 || pos + len > STR_END {
+        return Err(EepromError::InvalidString(pos).into());
+    }
+    // UTF-16LE, but only ASCII is ever stored so take the low bytes
+    let s = data[pos + 2..pos + len]
+        .iter()
+        .step_by(2)
+        .map(|b| *b as char)
+        .collect();
+    Ok((s, pos + len))
+}
+
+/// Write `s` as a USB string descriptor at `pos`, returning the position after it
+fn set_string_descriptor(data: &mut [u8], pos: usize, s: &str) -> Result<usize> {
+    if !s.is_ascii() {
+        return Err(EepromError::NonAsciiString(s.to_string()).into());
+    }
+    let len = 2 + 2 * s.len();
+    if pos + len > STR_END {
+        return Err(EepromError::StringTooLong {
+            s: s.to_string(),
+            max: STR_END - STR_OFFSET,
+        }
+        .into());
+    }
+    data[pos] = len as u8;
+    data[pos + 1] = USB_DT_STRING;
+    for (i, b) in s.bytes().enumerate() {
+        data[pos + 2 + 2 * i] = b;
+        data[pos + 3 + 2 * i] = 0x00;
+    }
+    Ok(pos + len)
+}
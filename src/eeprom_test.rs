@@ -0,0 +1,59 @@
+use crate::eeprom::{EepromConfig, EEPROM_SIZE};
+use crate::error::{EepromError, RtlsdrError};
+
+// Start of a dump from a generic RTL2832U/R820T dongle
+const GENERIC_DUMP: [u8; 50] = [
+    0x28, 0x32, 0xda, 0x0b, 0x38, 0x28, 0xa5, 0x16, 0x02, 0x10, 0x03, 0x52, 0x00, 0x65, 0x00, 0x61,
+    0x00, 0x6c, 0x00, 0x74, 0x00, 0x65, 0x00, 0x6b, 0x00, 0x1c, 0x03, 0x52, 0x00, 0x54, 0x00, 0x4c,
+    0x00, 0x32, 0x00, 0x38, 0x00, 0x33, 0x00, 0x38, 0x00, 0x55, 0x00, 0x48, 0x00, 0x49, 0x00, 0x44,
+    0x00, 0x49,
+];
+
+fn generic_image() -> [u8; EEPROM_SIZE] {
+    let mut buf = [0xff; EEPROM_SIZE];
+    EepromConfig::default().write_to(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn test_parse_generic() {
+    let buf = generic_image();
+    assert_eq!(buf[..GENERIC_DUMP.len()], GENERIC_DUMP);
+    let config = EepromConfig::parse(&buf).unwrap();
+    assert_eq!(config, EepromConfig::default());
+}
+
+#[test]
+fn test_write_keeps_ir_config() {
+    let mut buf = generic_image();
+    let config = EepromConfig {
+        serial: "RACK0006".to_string(),
+        enable_ir: false,
+        ..Default::default()
+    };
+    config.write_to(&mut buf).unwrap();
+    assert_eq!(buf[7] & 0x02, 0);
+    assert_eq!(buf[79..], [0xff; EEPROM_SIZE - 79]);
+    assert_eq!(EepromConfig::parse(&buf).unwrap(), config);
+}
+
+#[test]
+fn test_string_too_long() {
+    let mut buf = generic_image();
+    let config = EepromConfig {
+        product: "X".repeat(40),
+        ..Default::default()
+    };
+    assert!(matches!(
+        config.write_to(&mut buf),
+        Err(RtlsdrError::Eeprom(EepromError::StringTooLong { .. }))
+    ));
+}
+
+#[test]
+fn test_parse_blank() {
+    assert!(matches!(
+        EepromConfig::parse(&[0xff; EEPROM_SIZE]),
+        Err(RtlsdrError::Eeprom(EepromError::InvalidHeader(0xff, 0xff)))
+    ));
+}
@@ -1,11 +1,214 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
 use thiserror::Error;
 
+use crate::{GainStage, TunerType};
+
 #[derive(Debug, Error)]
 pub enum RtlsdrError {
     #[error("RtlSdr error {0}")]
     RtlsdrErr(String),
     #[error("USB error")]
-    Usb(#[from] rusb::Error),
+    Usb(#[source] rusb::Error),
+    /// A control transfer to a device register failed. For I2C the address is the I2C
+    /// address of the chip.
+    #[error("USB error accessing {block:?} register {addr:#06x}")]
+    Register {
+        block: RegisterBlock,
+        addr: u16,
+        #[source]
+        source: rusb::Error,
+    },
+    #[error("USB transfer timed out{}", accessing(.register))]
+    Timeout { register: Option<RegisterAddr> },
+    #[error("Device disconnected")]
+    DeviceDisconnected,
+    #[error("No device found matching {0}")]
+    DeviceNotFound(String),
+    #[error("{count} devices match {selector}, select by serial or port path instead")]
+    MultipleDevices { selector: String, count: usize },
+    #[error("Device is in use by another program{}", accessing(.register))]
+    DeviceBusy { register: Option<RegisterAddr> },
+    #[error("Device is claimed by a kernel driver that could not be detached")]
+    KernelDriverActive,
+    #[error("Hotplug is not supported by libusb on this platform")]
+    HotplugUnsupported,
+    /// The watcher thread stopped before it started watching
+    #[error("Hotplug watcher thread exited")]
+    HotplugWatcherExited,
+    #[error("No supported tuner found")]
+    TunerNotFound,
+    #[error("Unsupported tuner {0}")]
+    UnsupportedTuner(&'static str),
+    #[error("Invalid sample rate {rate} Hz, supported ranges are {supported:?}")]
+    InvalidSampleRate {
+        rate: u32,
+        supported: &'static [RangeInclusive<u32>],
+    },
+    #[error("RTL2832 crystal frequency {freq} Hz is out of the supported range {supported:?}")]
+    InvalidXtalFreq {
+        freq: u32,
+        supported: RangeInclusive<u32>,
+    },
+    #[error("Frequency correction {ppm} ppm is out of the supported range {supported:?} ppm")]
+    InvalidFreqCorrection {
+        ppm: f64,
+        supported: RangeInclusive<f64>,
+    },
+    #[error("Frequency {freq} Hz is out of the tuner's range, supported ranges are {ranges:?}")]
+    FrequencyOutOfRange {
+        freq: u64,
+        ranges: Vec<RangeInclusive<u64>>,
+    },
+    #[error("Invalid {stage:?} gain index {index}, supported indices are {supported:?}")]
+    InvalidGainIndex {
+        stage: GainStage,
+        index: u8,
+        supported: RangeInclusive<u8>,
+    },
+    #[error("{tuner:?} tuners have no separate {stage:?} gain control")]
+    GainStageUnsupported { stage: GainStage, tuner: TunerType },
+    /// Gains are in tenths of a dB
+    #[error("Invalid {stage:?} gain {gain}, supported gains are {supported:?}")]
+    InvalidGain {
+        stage: GainStage,
+        gain: i32,
+        supported: Vec<i32>,
+    },
+    /// Gains are in tenths of a dB
+    #[error("Invalid IF stage {stage} gain {gain}, supported gains are {supported:?}")]
+    InvalidIfGain {
+        stage: i32,
+        gain: i32,
+        supported: Vec<i32>,
+    },
+    #[error("Invalid IF stage {stage}, supported stages are {supported:?}")]
+    InvalidIfStage {
+        stage: i32,
+        supported: RangeInclusive<i32>,
+    },
+    /// R82xx tuners already use a low IF, offset tuning is for zero-IF tuners
+    #[error("Offset tuning is not supported on {tuner:?} tuners")]
+    OffsetTuningUnsupported { tuner: TunerType },
+    #[error("Offset tuning is not supported in direct sampling mode")]
+    OffsetTuningInDirectSampling,
+    #[error("Tuner PLL failed to lock at {freq} Hz")]
+    PllNotLocked { freq: u64 },
+    #[error("{tuner:?} tuners have no crystal capacitor setting")]
+    XtalCapUnsupported { tuner: TunerType },
+    /// No capacitor value let the tuner's PLL lock
+    #[error("Unable to find a good crystal capacitor value")]
+    XtalCalibrationFailed,
+    #[error("Invalid async buffer length {len}, must be a multiple of {multiple}")]
+    InvalidBufferLength { len: usize, multiple: usize },
+    #[error("FIR coefficient {index} out of range: {value}")]
+    InvalidFirCoefficient { index: usize, value: i32 },
+    #[error("Invalid register width {0}, must be 1 or 2 bytes")]
+    InvalidRegisterWidth(usize),
+    #[error("EEPROM error: {0}")]
+    Eeprom(#[from] EepromError),
+    #[error("rtl_tcp socket error")]
+    Io(#[from] std::io::Error),
+    #[error("Unable to listen on {addr}")]
+    Listen {
+        addr: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Not an rtl_tcp server, got header {header:02x?}")]
+    NotRtlTcpServer { header: [u8; 4] },
+    #[error("Unknown rtl_tcp command {0:#04x}")]
+    UnknownCommand(u8),
+    #[error("Gain index {index} out of range, tuner has {count} gains")]
+    GainIndexOutOfRange { index: u32, count: usize },
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EepromError {
+    #[error("image too short: {0} bytes")]
+    ImageTooShort(usize),
+    #[error("invalid RTL2832 header: {0:#04x} {1:#04x}")]
+    InvalidHeader(u8, u8),
+    #[error("invalid string descriptor at offset {0:#04x}")]
+    InvalidString(usize),
+    #[error("strings must be ASCII: \"{0}\"")]
+    NonAsciiString(String),
+    #[error("string \"{s}\" does not fit, manufacturer, product and serial may use {max} bytes in total")]
+    StringTooLong { s: String, max: usize },
+    #[error("access of {len} bytes at offset {offset} out of range")]
+    OutOfRange { offset: usize, len: usize },
+    #[error("write failed at offset {0:#04x}")]
+    WriteFailed(u8),
+    #[error("verification failed at offset {offset:#04x}: wrote {wrote:#04x}, read {read:#04x}")]
+    VerifyFailed { offset: usize, wrote: u8, read: u8 },
+}
+
+/// Register block addressed by a control transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterBlock {
+    /// Demod registers, `None` when the block is addressed directly rather than by page
+    Demod {
+        page: Option<u16>,
+    },
+    Usb,
+    Sys,
+    Tuner,
+    Rom,
+    Ir,
+    I2c,
+    Other(u16),
+}
+
+/// Register a failed control transfer was accessing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterAddr {
+    pub block: RegisterBlock,
+    pub addr: u16,
+}
+
+impl fmt::Display for RegisterAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} register {:#06x}", self.block, self.addr)
+    }
+}
+
+fn accessing(register: &Option<RegisterAddr>) -> String {
+    register
+        .map(|r| format!(" accessing {r}"))
+        .unwrap_or_default()
+}
+
+impl From<rusb::Error> for RtlsdrError {
+    fn from(e: rusb::Error) -> Self {
+        match e {
+            // libusb reports a device that has gone away as NoDevice on every call
+            rusb::Error::NoDevice => RtlsdrError::DeviceDisconnected,
+            rusb::Error::Timeout => RtlsdrError::Timeout { register: None },
+            rusb::Error::Busy => RtlsdrError::DeviceBusy { register: None },
+            e => RtlsdrError::Usb(e),
+        }
+    }
+}
+
+impl RtlsdrError {
+    /// Record which register a failed USB transfer was accessing
+    pub(crate) fn at_register(self, block: RegisterBlock, addr: u16) -> Self {
+        match self {
+            RtlsdrError::Usb(source) => RtlsdrError::Register {
+                block,
+                addr,
+                source,
+            },
+            RtlsdrError::Timeout { register: None } => RtlsdrError::Timeout {
+                register: Some(RegisterAddr { block, addr }),
+            },
+            RtlsdrError::DeviceBusy { register: None } => RtlsdrError::DeviceBusy {
+                register: Some(RegisterAddr { block, addr }),
+            },
+            e => e,
+        }
+    }
 }
 
 /// A result of a function that may return a `Error`.
@@ -0,0 +1,176 @@
+//! USB hotplug notifications for RTL-SDR devices
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{error, info};
+use rusb::{Context, Hotplug, HotplugBuilder, UsbContext};
+
+use crate::device::device_handle::known_devices;
+use crate::device::KNOWN_DEVICES;
+use crate::error::Result;
+use crate::error::RtlsdrError::{HotplugUnsupported, HotplugWatcherExited};
+use crate::{device_info, DeviceInfo};
+
+/// How long the watcher thread blocks in libusb before checking for shutdown
+const EVENT_TIMEOUT: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone)]
+pub enum HotplugEvent {
+    /// A supported device was plugged in, or was already attached when the watcher started
+    Arrived(DeviceInfo),
+    /// A device was unplugged. Carries the info reported when it arrived.
+    Left(DeviceInfo),
+}
+
+/// Watches the USB bus on a background thread and reports RTL-SDR devices coming and
+/// going. Watching stops when the watcher is dropped.
+#[derive(Debug)]
+pub struct HotplugWatcher {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+enum RawEvent {
+    Arrived(rusb::Device<Context>),
+    Left(rusb::Device<Context>),
+}
+
+/// Forwards libusb callbacks to the watcher thread. Descriptors can't be read from
+/// inside the callback, so DeviceInfo is built afterwards.
+struct Forwarder {
+    tx: Sender<RawEvent>,
+}
+
+impl Hotplug<Context> for Forwarder {
+    fn device_arrived(&mut self, device: rusb::Device<Context>) {
+        self.tx.send(RawEvent::Arrived(device)).ok();
+    }
+
+    fn device_left(&mut self, device: rusb::Device<Context>) {
+        self.tx.send(RawEvent::Left(device)).ok();
+    }
+}
+
+impl HotplugWatcher {
+    /// Start watching, calling `callback` from the watcher thread for every event.
+    /// Devices already attached are reported as arrivals first.
+    pub fn new<F>(mut callback: F) -> Result<HotplugWatcher>
+    where
+        F: FnMut(HotplugEvent) + Send + 'static,
+    {
+        if !rusb::has_hotplug() {
+            return Err(HotplugUnsupported);
+        }
+        let context = Context::new()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let thread_shutdown = shutdown.clone();
+        let thread = thread::spawn(move || {
+            let (tx, rx) = mpsc::channel();
+            // Registration isn't Send, so it has to be created on this thread
+            let registration = HotplugBuilder::new()
+                .enumerate(true)
+                .register::<Context, _>(&context, Box::new(Forwarder { tx }));
+            let _registration = match registration {
+                Ok(r) => {
+                    ready_tx.send(Ok(())).ok();
+                    r
+                }
+                Err(e) => {
+                    ready_tx.send(Err(e)).ok();
+                    return;
+                }
+            };
+
+            // Info for attached devices, keyed by bus number and address
+            let mut attached: HashMap<(u8, u8), DeviceInfo> = HashMap::new();
+            while !thread_shutdown.load(Ordering::Relaxed) {
+                if let Err(e) = context.handle_events(Some(EVENT_TIMEOUT)) {
+                    error!("Hotplug event handling failed: {}", e);
+                    break;
+                }
+                for event in rx.try_iter() {
+                    match event {
+                        RawEvent::Arrived(device) => {
+                            if !is_known(&device) {
+                                continue;
+                            }
+                            let index = known_devices(&context)
+                                .ok()
+                                .and_then(|devs| {
+                                    devs.iter().position(|d| {
+                                        d.bus_number() == device.bus_number()
+                                            && d.address() == device.address()
+                                    })
+                                })
+                                .unwrap_or(0);
+                            match device_info(index, &device) {
+                                Ok(info) => {
+                                    info!("Device arrived: {:?}", info);
+                                    attached.insert(
+                                        (device.bus_number(), device.address()),
+                                        info.clone(),
+                                    );
+                                    callback(HotplugEvent::Arrived(info));
+                                }
+                                Err(e) => error!("Unable to read arriving device: {}", e),
+                            }
+                        }
+                        RawEvent::Left(device) => {
+                            if let Some(info) =
+                                attached.remove(&(device.bus_number(), device.address()))
+                            {
+                                info!("Device left: {:?}", info);
+                                callback(HotplugEvent::Left(info));
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(HotplugWatcher {
+                shutdown,
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => {
+                thread.join().ok();
+                Err(e.into())
+            }
+            Err(_) => Err(HotplugWatcherExited),
+        }
+    }
+
+    /// Stop watching and wait for the watcher thread to exit
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn is_known(device: &rusb::Device<Context>) -> bool {
+    match device.device_descriptor() {
+        Ok(desc) => KNOWN_DEVICES
+            .iter()
+            .any(|dev| desc.vendor_id() == dev.vid && desc.product_id() == dev.pid),
+        Err(_) => false,
+    }
+}
@@ -2,24 +2,146 @@
 //! Library for interfacing with an RTL-SDR device.
 
 mod device;
+pub mod eeprom;
+#[cfg(test)]
+mod eeprom_test;
 pub mod error;
+pub mod hotplug;
+pub mod rtl_tcp;
+#[cfg(test)]
+mod rtl_tcp_test;
 mod rtlsdr;
+#[cfg(test)]
+mod rtlsdr_test;
 mod tuners;
+pub mod virtual_device;
+#[cfg(test)]
+mod virtual_device_test;
 
+use device::device_handle::known_devices;
 use device::Device;
+pub use device::Transport;
 use device::KNOWN_DEVICES;
+use eeprom::{EepromConfig, EEPROM_SIZE};
 use error::Result;
+use log::{info, warn};
 use rtlsdr::RtlSdr as Sdr;
 
 use rusb::{Context, UsbContext};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use virtual_device::VirtualConfig;
 
 pub const DEFAULT_BUF_LENGTH: usize = 16 * 16384;
+/// Number of bulk transfers kept in flight by `read_async` by default
+pub const DEFAULT_BUF_NUMBER: usize = 15;
+/// Sample rates the RTL2832 resampler supports
+pub const SAMPLE_RATE_RANGES: [RangeInclusive<u32>; 2] = [225_001..=300_000, 900_001..=3_200_000];
 
 #[derive(Debug, Clone)]
 pub enum TunerGain {
     Auto,
     Manual(i32),
 }
+/// Separately controllable gain stage of a tuner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GainStage {
+    Lna,
+    Mixer,
+    Vga,
+}
+/// Setting of a single gain stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageGain {
+    /// Gain controlled by the stage's AGC
+    Auto,
+    /// Index into the stage's gain table, see `RtlSdr::get_tuner_stage_gains`
+    Index(u8),
+}
+/// Crystal load capacitor of R82xx tuners, from the most to the least capacitance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum XtalCapValue {
+    XtalLowCap30p,
+    XtalLowCap20p,
+    XtalLowCap10p,
+    XtalLowCap0p,
+    XtalHighCap0p,
+}
+/// How R82xx tuners choose their crystal load capacitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XtalCap {
+    /// Search for a value the PLL locks well with when the tuner initialises
+    Calibrate,
+    Fixed(XtalCapValue),
+}
+/// Tuner chip, numbered like librtlsdr's `rtlsdr_tuner` enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunerType {
+    Unknown = 0,
+    E4000 = 1,
+    FC0012 = 2,
+    FC0013 = 3,
+    FC2580 = 4,
+    R820T = 5,
+    R828D = 6,
+}
+
+impl From<u32> for TunerType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => TunerType::E4000,
+            2 => TunerType::FC0012,
+            3 => TunerType::FC0013,
+            4 => TunerType::FC2580,
+            5 => TunerType::R820T,
+            6 => TunerType::R828D,
+            _ => TunerType::Unknown,
+        }
+    }
+}
+/// Frequency translation of an up- or downconverter in front of the dongle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FreqTranslation {
+    /// Added to the RF frequency to get the frequency at the dongle in Hz. The LO for
+    /// upconverters, minus the LO for downconverters such as LNBs.
+    pub lo_offset: i64,
+    /// The converter LO is above the RF, so the dongle frequency is `lo_offset` minus the RF
+    /// frequency and the spectrum arrives mirrored. The demod's spectrum inversion undoes
+    /// it, so the samples come out the right way round.
+    pub inverted: bool,
+}
+
+impl FreqTranslation {
+    /// Frequency at the dongle for an RF frequency, `None` if it's out of the u32 range
+    pub fn dongle_freq(&self, rf_freq: u64) -> Option<u32> {
+        let rf_freq = i128::from(rf_freq);
+        let lo_offset = i128::from(self.lo_offset);
+        let freq = if self.inverted {
+            lo_offset - rf_freq
+        } else {
+            rf_freq + lo_offset
+        };
+        u32::try_from(freq).ok()
+    }
+}
+
+/// RTL2832 and tuner crystal frequencies in Hz
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XtalFreqs {
+    pub rtl: u32,
+    pub tuner: u32,
+}
+
+/// Crystals a dongle model ships with, by tuner type and whether it's an RTL-SDR Blog V4.
+/// Most tuners run from the RTL2832's 28.8 MHz clock, the FC2580 and non-Blog R828D
+/// dongles have their own crystal.
+pub fn default_xtal_freqs(tuner_type: TunerType, blog_v4: bool) -> XtalFreqs {
+    rtlsdr::default_xtal_freqs(tuner_type, blog_v4)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum DirectSampleMode {
     Off,
@@ -27,43 +149,189 @@ pub enum DirectSampleMode {
     OnSwap, // Swap I and Q ADC, allowing to select between two inputs
 }
 
+/// Settings a buffer from `RtlSdr::read_async_with_info` was received with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferInfo {
+    /// RF center frequency in Hz, see `RtlSdr::get_center_freq_u64`
+    pub center_freq: u64,
+    pub sample_rate: u32,
+}
+
+/// Stops a running `RtlSdr::read_async` from another thread
+#[derive(Debug, Clone)]
+pub struct AsyncCanceller {
+    cancel: Arc<AtomicBool>,
+}
+impl AsyncCanceller {
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Identifies the device `RtlSdr::open` should open. A plain `usize` converts into
+/// `Index`, so `RtlSdr::open(0)` opens the first device.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceSelector {
+    /// Position in `enumerate()` order
+    Index(usize),
+    /// USB serial number string, as reported by `enumerate()`
+    Serial(String),
+    /// USB bus number and device address. The address changes on every replug.
+    BusAddress { bus: u8, address: u8 },
+    /// USB bus number and physical port path, e.g. bus 1 ports [2, 3] for `1-2.3`
+    PortPath { bus: u8, ports: Vec<u8> },
+    /// Vendor and product ID, which must match exactly one attached device
+    VidPid { vid: u16, pid: u16 },
+    /// Software-emulated RTL2832U, no hardware needed. `VirtualConfig::tuner` picks the
+    /// emulated tuner: R820T, R828D or E4000.
+    Virtual(Box<VirtualConfig>),
+}
+
+impl From<usize> for DeviceSelector {
+    fn from(index: usize) -> Self {
+        DeviceSelector::Index(index)
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Index(index) => write!(f, "index {index}"),
+            DeviceSelector::Serial(serial) => write!(f, "serial \"{serial}\""),
+            DeviceSelector::BusAddress { bus, address } => {
+                write!(f, "bus {bus} address {address}")
+            }
+            DeviceSelector::PortPath { bus, ports } => {
+                let ports: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+                write!(f, "port path {bus}-{}", ports.join("."))
+            }
+            DeviceSelector::VidPid { vid, pid } => write!(f, "VID:PID {vid:04x}:{pid:04x}"),
+            DeviceSelector::Virtual(_) => write!(f, "virtual device"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DeviceInfo {
+    /// Index to pass to `RtlSdr::open`
     pub index: usize,
+    /// USB serial number, empty if it could not be read
     pub serial: String,
+    /// Model description from the list of known devices
+    pub description: &'static str,
+    /// USB manufacturer string, empty if it could not be read
+    pub manufacturer: String,
+    /// USB product string, empty if it could not be read
+    pub product: String,
+    pub vid: u16,
+    pub pid: u16,
+    pub bus_number: u8,
+    pub address: u8,
+    /// Physical port path from the root hub, e.g. [2, 3] for `1-2.3` on bus 1
+    pub port_numbers: Vec<u8>,
+    /// False if the device is in use by another process and cannot be opened
+    pub available: bool,
+    /// Detected tuner name, only filled in by `enumerate_probe`
+    pub tuner: Option<&'static str>,
 }
 
+/// List attached RTL-SDR devices. Devices that are busy are still listed, with
+/// `available` set to false.
 pub fn enumerate() -> Result<Vec<DeviceInfo>> {
     let context = Context::new()?;
-    let devices = context.devices()?;
-
     let mut devs = Vec::new();
-    let mut index = 0;
-
-    for found in devices.iter() {
-        let device_desc = found.device_descriptor()?;
-        for dev in KNOWN_DEVICES.iter() {
-            if device_desc.vendor_id() == dev.vid && device_desc.product_id() == dev.pid {
-                let dev = found.open()?;
-                let serial = dev.read_serial_number_string_ascii(&device_desc)?;
-                devs.push(DeviceInfo { index, serial });
-                index += 1;
+    for (index, found) in known_devices(&context)?.iter().enumerate() {
+        devs.push(device_info(index, found)?);
+    }
+    Ok(devs)
+}
+
+/// Like `enumerate`, but also briefly opens each available device to detect its tuner
+pub fn enumerate_probe() -> Result<Vec<DeviceInfo>> {
+    let mut devs = enumerate()?;
+    for info in devs.iter_mut().filter(|info| info.available) {
+        let selector = DeviceSelector::BusAddress {
+            bus: info.bus_number,
+            address: info.address,
+        };
+        let probed = Device::new(&selector).and_then(|dev| Sdr::new(dev).probe_tuner());
+        match probed {
+            Ok(tuner) => info.tuner = tuner.map(|t| t.name),
+            Err(e) => {
+                warn!("Unable to probe tuner of device {}: {}", info.index, e);
+                info.available = false;
             }
         }
     }
     Ok(devs)
 }
 
+pub(crate) fn device_info<T: UsbContext>(
+    index: usize,
+    found: &rusb::Device<T>,
+) -> Result<DeviceInfo> {
+    let desc = found.device_descriptor()?;
+    let description = KNOWN_DEVICES
+        .iter()
+        .find(|dev| dev.vid == desc.vendor_id() && dev.pid == desc.product_id())
+        .map_or("", |dev| dev.description);
+    let mut info = DeviceInfo {
+        index,
+        serial: String::new(),
+        description,
+        manufacturer: String::new(),
+        product: String::new(),
+        vid: desc.vendor_id(),
+        pid: desc.product_id(),
+        bus_number: found.bus_number(),
+        address: found.address(),
+        port_numbers: found.port_numbers().unwrap_or_default(),
+        available: false,
+        tuner: None,
+    };
+    let handle = match found.open() {
+        Ok(handle) => handle,
+        Err(e) => {
+            info!("Unable to open device {}: {}", index, e);
+            return Ok(info);
+        }
+    };
+    info.serial = handle
+        .read_serial_number_string_ascii(&desc)
+        .unwrap_or_default();
+    info.manufacturer = handle
+        .read_manufacturer_string_ascii(&desc)
+        .unwrap_or_default();
+    info.product = handle.read_product_string_ascii(&desc).unwrap_or_default();
+    // A kernel driver holding the interface is fine, open() detaches it
+    info.available = match handle.claim_interface(0) {
+        Ok(()) => {
+            handle.release_interface(0).ok();
+            true
+        }
+        Err(rusb::Error::Busy) => handle.kernel_driver_active(0).unwrap_or(false),
+        Err(_) => false,
+    };
+    Ok(info)
+}
+
 pub struct RtlSdr {
     sdr: Sdr,
 }
 impl RtlSdr {
-    pub fn open(index: usize) -> Result<RtlSdr> {
-        let dev = Device::new(index)?;
+    /// Open a device by index or any other `DeviceSelector`
+    pub fn open<S: Into<DeviceSelector>>(selector: S) -> Result<RtlSdr> {
+        let dev = Device::new(&selector.into())?;
         let mut sdr = Sdr::new(dev);
         sdr.init()?;
         Ok(RtlSdr { sdr })
     }
+    /// Open a device over a custom `Transport` instead of libusb, e.g. a mock or emulator
+    pub fn open_transport(transport: Box<dyn Transport>) -> Result<RtlSdr> {
+        let mut sdr = Sdr::new(Device::from_transport(transport));
+        sdr.init()?;
+        Ok(RtlSdr { sdr })
+    }
     pub fn close(&mut self) -> Result<()> {
         // TODO: wait until async is inactive
         self.sdr.deinit_baseband()
@@ -74,11 +342,134 @@ impl RtlSdr {
     pub fn read_sync(&self, buf: &mut [u8]) -> Result<usize> {
         self.sdr.read_sync(buf)
     }
+    /// Read the whole EEPROM, e.g. to back it up before changing it
+    pub fn dump_eeprom(&self) -> Result<[u8; EEPROM_SIZE]> {
+        self.sdr.dump_eeprom()
+    }
+    /// Write a whole EEPROM image, verifying it afterwards
+    pub fn restore_eeprom(&self, data: &[u8; EEPROM_SIZE]) -> Result<()> {
+        self.sdr.restore_eeprom(data)
+    }
+    pub fn get_eeprom_config(&self) -> Result<EepromConfig> {
+        self.sdr.get_eeprom_config()
+    }
+    /// Write a new EEPROM configuration, e.g. to give the device a unique serial.
+    /// The device has to be replugged for USB descriptor changes to show up.
+    pub fn set_eeprom_config(&self, config: &EepromConfig) -> Result<()> {
+        self.sdr.set_eeprom_config(config)
+    }
+    /// Stream samples to `cb` until cancelled, keeping `buf_num` bulk transfers of
+    /// `buf_len` bytes queued. Pass 0 for either to use `DEFAULT_BUF_NUMBER`/`DEFAULT_BUF_LENGTH`.
+    /// Blocks the calling thread; stop it with `cancel_async` from the callback or with an
+    /// `AsyncCanceller` from another thread. A cancel that arrives before the read starts
+    /// makes it return straight away. See `read_async_with_info` for the RF frequency of each
+    /// buffer.
+    pub fn read_async<F: FnMut(&[u8])>(&self, buf_num: u32, buf_len: u32, mut cb: F) -> Result<()> {
+        self.sdr.read_async(buf_num, buf_len, &mut cb)
+    }
+    /// Like `read_async`, but also passes the frequency and sample rate in effect when each
+    /// buffer completed. Samples already queued in the device or in transfers when a retune
+    /// happens, up to `buf_num` buffers, still arrive with the new frequency.
+    pub fn read_async_with_info<F: FnMut(&[u8], BufferInfo)>(
+        &self,
+        buf_num: u32,
+        buf_len: u32,
+        mut cb: F,
+    ) -> Result<()> {
+        self.sdr.read_async_with_info(buf_num, buf_len, &mut cb)
+    }
+    /// Like `read_async`, but sends each buffer down `tx`. Returns once cancelled or when
+    /// the receiving end is dropped.
+    pub fn read_async_channel(
+        &self,
+        buf_num: u32,
+        buf_len: u32,
+        tx: Sender<Vec<u8>>,
+    ) -> Result<()> {
+        self.sdr.read_async(buf_num, buf_len, &mut |buf| {
+            if tx.send(buf.to_vec()).is_err() {
+                self.sdr.cancel_async();
+            }
+        })
+    }
+    pub fn cancel_async(&self) {
+        self.sdr.cancel_async()
+    }
+    pub fn async_canceller(&self) -> AsyncCanceller {
+        AsyncCanceller {
+            cancel: self.sdr.async_cancel_flag(),
+        }
+    }
+    /// RF center frequency in Hz, saturating at `u32::MAX`. See `get_center_freq_u64`.
     pub fn get_center_freq(&self) -> u32 {
-        self.sdr.get_center_freq()
+        u32::try_from(self.sdr.get_rf_freq()).unwrap_or(u32::MAX)
     }
     pub fn set_center_freq(&self, freq: u32) -> Result<()> {
-        self.sdr.set_center_freq(freq)
+        self.sdr.set_rf_freq(freq as u64)
+    }
+    /// RF center frequency in Hz, before the frequency translation
+    pub fn get_center_freq_u64(&self) -> u64 {
+        self.sdr.get_rf_freq()
+    }
+    /// Tune to an RF frequency in Hz. The dongle tunes to its translation, which has to fit
+    /// a u32 and the tuner's range.
+    pub fn set_center_freq_u64(&self, freq: u64) -> Result<()> {
+        self.sdr.set_rf_freq(freq)
+    }
+    /// Frequency the dongle is tuned to in Hz, after the frequency translation
+    pub fn get_dongle_freq(&self) -> u32 {
+        self.sdr.get_center_freq()
+    }
+    pub fn get_freq_translation(&self) -> FreqTranslation {
+        self.sdr.get_freq_translation()
+    }
+    /// Configure the up- or downconverter in front of the dongle. The RF center frequency
+    /// is kept, retuning the dongle if it's already tuned.
+    pub fn set_freq_translation(&self, translation: FreqTranslation) -> Result<()> {
+        self.sdr.set_freq_translation(translation)
+    }
+    /// Actual tuner LO frequency of the last retune in Hz, after PLL quantisation
+    pub fn get_tuner_lo_freq(&self) -> Option<f64> {
+        self.sdr.get_tuner_lo_freq()
+    }
+    /// Actual IF the demod shifts to 0 Hz, in Hz
+    pub fn get_if_freq(&self) -> f64 {
+        self.sdr.get_if_freq()
+    }
+    /// RF frequency at 0 Hz in the samples minus the center frequency, in Hz. Subtract it
+    /// digitally for exact tuning. `None` if the tuner can't compute its LO. Errors of the
+    /// external converter aren't included.
+    pub fn get_tuning_error(&self) -> Option<f64> {
+        self.sdr.get_tuning_error()
+    }
+    /// IF filter calibration code found when the tuner initialised, for diagnostics.
+    /// `None` for tuners without filter calibration.
+    pub fn get_tuner_filter_cal_code(&self) -> Option<u8> {
+        self.sdr.get_tuner_filter_cal_code()
+    }
+    /// Crystal load capacitor in use. `None` for tuners without one.
+    pub fn get_xtal_cap(&self) -> Option<XtalCapValue> {
+        self.sdr.get_xtal_cap()
+    }
+    /// Set the R82xx crystal load capacitor, or run the calibration that searches for one.
+    /// The default is `XtalHighCap0p`, which a failed calibration also falls back to.
+    /// Gain and bandwidth settings are kept.
+    pub fn set_xtal_cap(&self, cap: XtalCap) -> Result<()> {
+        self.sdr.set_xtal_cap(cap)
+    }
+    /// Whether the tuner PLL locked on the last retune, `None` if the tuner can't tell
+    pub fn get_pll_locked(&self) -> Option<bool> {
+        self.sdr.get_pll_locked()
+    }
+    /// Make `set_center_freq` fail with `PllNotLocked` when the tuner PLL doesn't lock,
+    /// instead of only logging it. On failure the tuner is retuned to the previous center
+    /// frequency, if there was one.
+    pub fn set_require_pll_lock(&self, on: bool) {
+        self.sdr.set_require_pll_lock(on)
+    }
+    /// Frequency ranges the tuner can tune to, in Hz
+    pub fn get_tuner_freq_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        self.sdr.get_tuner_freq_ranges()
     }
     pub fn get_tuner_gains(&self) -> Result<Vec<i32>> {
         self.sdr.get_tuner_gains()
@@ -86,28 +477,173 @@ impl RtlSdr {
     pub fn set_tuner_gain(&self, gain: TunerGain) -> Result<()> {
         self.sdr.set_tuner_gain(gain)
     }
+    pub fn get_tuner_type(&self) -> TunerType {
+        self.sdr.get_tuner_type()
+    }
+    /// Gain of each index of a stage in tenths of a dB. Empty for tuners without
+    /// separately controllable stages.
+    pub fn get_tuner_stage_gains(&self, stage: GainStage) -> Result<Vec<i32>> {
+        self.sdr.get_tuner_stage_gains(stage)
+    }
+    /// Set one gain stage directly, bypassing the combined gain of `set_tuner_gain`.
+    /// Only the R820T and R828D support this.
+    pub fn set_tuner_stage_gain(&self, stage: GainStage, gain: StageGain) -> Result<()> {
+        self.sdr.set_tuner_stage_gain(stage, gain)
+    }
+    /// Set the gain of one IF stage in tenths of a dB. Ignored by tuners without IF gain control.
+    pub fn set_tuner_if_gain(&self, stage: i32, gain: i32) -> Result<()> {
+        self.sdr.set_tuner_if_gain(stage, gain)
+    }
+    /// Enable or disable the RTL2832's digital AGC
+    pub fn set_agc_mode(&self, on: bool) -> Result<()> {
+        self.sdr.set_agc_mode(on)
+    }
     pub fn get_freq_correction(&self) -> i32 {
         self.sdr.get_freq_correction()
     }
     pub fn set_freq_correction(&self, ppm: i32) -> Result<()> {
         self.sdr.set_freq_correction(ppm)
     }
+    /// Frequency correction in ppm, with fractions
+    pub fn get_freq_correction_ppm(&self) -> f64 {
+        self.sdr.get_freq_correction_ppm()
+    }
+    /// Correct for a crystal running `ppm` fast, with fractions. Applies to the sample rate,
+    /// the demod IF and the tuner PLL. The limit is ±488 ppm.
+    pub fn set_freq_correction_ppm(&self, ppm: f64) -> Result<()> {
+        self.sdr.set_freq_correction_ppm(ppm)
+    }
+    /// RTL2832 clock in Hz after frequency correction
+    pub fn get_xtal_freq(&self) -> u32 {
+        self.sdr.get_xtal_freq()
+    }
+    /// Tuner PLL reference in Hz after frequency correction
+    pub fn get_tuner_xtal_freq(&self) -> u32 {
+        self.sdr.get_tuner_xtal_freq()
+    }
+    /// Crystal frequencies as configured, before frequency correction
+    pub fn get_xtal_freqs(&self) -> XtalFreqs {
+        self.sdr.get_xtal_freqs()
+    }
+    /// Set the crystal frequencies in Hz, for dongles with a TCXO, an external reference or a
+    /// tuner crystal that differs from `default_xtal_freqs`. The RTL2832 needs 28.8 MHz ±1 kHz.
+    /// A frequency of 0 keeps the current crystal, so dongles with one shared crystal need
+    /// both set. The sample rate, IF and tuner PLL are reprogrammed from the new clocks.
+    pub fn set_xtal_freq(&self, rtl_freq: u32, tuner_freq: u32) -> Result<()> {
+        self.sdr.set_xtal_freq(rtl_freq, tuner_freq)
+    }
+    /// Go back to the model's `default_xtal_freqs` and reprogram the clocks
+    pub fn reset_xtal_freqs(&self) -> Result<()> {
+        self.sdr.reset_xtal_freqs()
+    }
     pub fn get_sample_rate(&self) -> u32 {
         self.sdr.get_sample_rate()
     }
     pub fn set_sample_rate(&self, rate: u32) -> Result<()> {
         self.sdr.set_sample_rate(rate)
     }
+    /// Set the tuner IF filter bandwidth, 0 to follow the sample rate. The tuner picks the
+    /// nearest bandwidth it supports, see `get_tuner_bandwidths`.
     pub fn set_tuner_bandwidth(&self, bw: u32) -> Result<()> {
         self.sdr.set_tuner_bandwidth(bw)
     }
+    /// Bandwidths the tuner IF filters support, in Hz, ascending
+    pub fn get_tuner_bandwidths(&self) -> Vec<u32> {
+        self.sdr.get_tuner_bandwidths()
+    }
+    /// Bandwidth the tuner IF filters are set to, in Hz
+    pub fn get_tuner_bandwidth(&self) -> u32 {
+        self.sdr.get_tuner_bandwidth()
+    }
+    /// IF the tuner mixes to, in Hz, 0 for zero-IF tuners. R82xx tuners pick it along with
+    /// the bandwidth.
+    pub fn get_tuner_if_freq(&self) -> Result<u32> {
+        self.sdr.get_tuner_if_freq()
+    }
     pub fn set_testmode(&self, on: bool) -> Result<()> {
         self.sdr.set_testmode(on)
     }
     pub fn set_direct_sampling(&self, mode: DirectSampleMode) -> Result<()> {
         self.sdr.set_direct_sampling(mode)
     }
+    /// Tune the zero-IF E4000, FC0012, FC0013 and FC2580 tuners below the center frequency and
+    /// shift the difference out in the demod, moving the DC spike out of the band. Errors on
+    /// R82xx tuners, which already use a low IF, unless `set_offset_tuning_bias_tee` is on.
+    pub fn set_offset_tuning(&self, on: bool) -> Result<()> {
+        self.sdr.set_offset_tuning(on)
+    }
+    pub fn get_offset_tuning(&self) -> bool {
+        self.sdr.get_offset_tuning()
+    }
+    /// Make `set_offset_tuning` switch the bias tee on R82xx tuners instead, for software
+    /// without bias tee support. This is the RTL-SDR Blog driver behaviour, on by default
+    /// with the `rtl_sdr_blog` feature.
+    pub fn set_offset_tuning_bias_tee(&self, on: bool) {
+        self.sdr.set_offset_tuning_bias_tee(on)
+    }
     pub fn set_bias_tee(&self, on: bool) -> Result<()> {
         self.sdr.set_bias_tee(on)
     }
 }
+
+/// Operations shared by a local `RtlSdr` and a remote `rtl_tcp::RtlTcpClient`, so code can
+/// work with either through `&dyn SdrDevice`
+pub trait SdrDevice {
+    fn get_tuner_type(&self) -> TunerType;
+    fn get_tuner_gains(&self) -> Result<Vec<i32>>;
+    fn set_tuner_gain(&self, gain: TunerGain) -> Result<()>;
+    /// RF center frequency in Hz, which is also what the samples from `read_sync` are
+    /// centred on
+    fn get_center_freq(&self) -> u32;
+    fn set_center_freq(&self, freq: u32) -> Result<()>;
+    fn get_sample_rate(&self) -> u32;
+    fn set_sample_rate(&self, rate: u32) -> Result<()>;
+    fn get_freq_correction(&self) -> i32;
+    fn set_freq_correction(&self, ppm: i32) -> Result<()>;
+    fn set_bias_tee(&self, on: bool) -> Result<()>;
+    fn set_direct_sampling(&self, mode: DirectSampleMode) -> Result<()>;
+    fn reset_buffer(&self) -> Result<()>;
+    fn read_sync(&self, buf: &mut [u8]) -> Result<usize>;
+}
+
+impl SdrDevice for RtlSdr {
+    fn get_tuner_type(&self) -> TunerType {
+        RtlSdr::get_tuner_type(self)
+    }
+    fn get_tuner_gains(&self) -> Result<Vec<i32>> {
+        RtlSdr::get_tuner_gains(self)
+    }
+    fn set_tuner_gain(&self, gain: TunerGain) -> Result<()> {
+        RtlSdr::set_tuner_gain(self, gain)
+    }
+    fn get_center_freq(&self) -> u32 {
+        RtlSdr::get_center_freq(self)
+    }
+    fn set_center_freq(&self, freq: u32) -> Result<()> {
+        RtlSdr::set_center_freq(self, freq)
+    }
+    fn get_sample_rate(&self) -> u32 {
+        RtlSdr::get_sample_rate(self)
+    }
+    fn set_sample_rate(&self, rate: u32) -> Result<()> {
+        RtlSdr::set_sample_rate(self, rate)
+    }
+    fn get_freq_correction(&self) -> i32 {
+        RtlSdr::get_freq_correction(self)
+    }
+    fn set_freq_correction(&self, ppm: i32) -> Result<()> {
+        RtlSdr::set_freq_correction(self, ppm)
+    }
+    fn set_bias_tee(&self, on: bool) -> Result<()> {
+        RtlSdr::set_bias_tee(self, on)
+    }
+    fn set_direct_sampling(&self, mode: DirectSampleMode) -> Result<()> {
+        RtlSdr::set_direct_sampling(self, mode)
+    }
+    fn reset_buffer(&self) -> Result<()> {
+        RtlSdr::reset_buffer(self)
+    }
+    fn read_sync(&self, buf: &mut [u8]) -> Result<usize> {
+        RtlSdr::read_sync(self, buf)
+    }
+}
@@ -0,0 +1,434 @@
+//! The rtl_tcp network protocol, a server that shares an `RtlSdr` with rtl_tcp clients, and
+//! a client for using remote devices.
+//!
+//! On connect the server sends a 12 byte dongle info header: the magic `RTL0`, then the tuner
+//! type and the number of gain steps as big endian u32s. After that it streams raw 8-bit IQ
+//! samples while the client sends 5 byte commands: a command byte followed by a big endian
+//! u32 parameter.
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+
+use log::{error, info, warn};
+use parking_lot::Mutex;
+
+use crate::error::Result;
+use crate::error::RtlsdrError;
+use crate::tuners;
+use crate::{DirectSampleMode, RtlSdr, SdrDevice, TunerGain, TunerType, DEFAULT_BUF_LENGTH};
+
+pub const MAGIC: &[u8; 4] = b"RTL0";
+pub const DONGLE_INFO_LEN: usize = 12;
+pub const COMMAND_LEN: usize = 5;
+/// Buffers queued for a slow client, newly read buffers are dropped while it's full
+pub const DEFAULT_MAX_QUEUED: usize = 500;
+
+/// Header sent to every client on connect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DongleInfo {
+    /// `TunerType` as a number
+    pub tuner_type: u32,
+    pub gain_count: u32,
+}
+
+impl DongleInfo {
+    pub fn to_bytes(&self) -> [u8; DONGLE_INFO_LEN] {
+        let mut buf = [0; DONGLE_INFO_LEN];
+        buf[0..4].copy_from_slice(MAGIC);
+        buf[4..8].copy_from_slice(&self.tuner_type.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.gain_count.to_be_bytes());
+        buf
+    }
+
+    pub fn parse(buf: &[u8; DONGLE_INFO_LEN]) -> Result<DongleInfo> {
+        if &buf[0..4] != MAGIC {
+            return Err(RtlsdrError::NotRtlTcpServer {
+                header: [buf[0], buf[1], buf[2], buf[3]],
+            });
+        }
+        Ok(DongleInfo {
+            tuner_type: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            gain_count: u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Center frequency in Hz
+    SetFrequency(u32),
+    SetSampleRate(u32),
+    /// True for manual gain, false for automatic
+    SetGainMode(bool),
+    /// Tuner gain in tenths of a dB
+    SetGain(i32),
+    SetFreqCorrection(i32),
+    /// IF stage and gain in tenths of a dB
+    SetIfGain {
+        stage: u16,
+        gain: i16,
+    },
+    SetTestMode(bool),
+    SetAgcMode(bool),
+    /// 0 off, 1 I branch, 2 Q branch
+    SetDirectSampling(u32),
+    SetOffsetTuning(bool),
+    SetRtlXtal(u32),
+    SetTunerXtal(u32),
+    /// Index into the tuner's gain list
+    SetGainByIndex(u32),
+    SetBiasTee(bool),
+}
+
+impl Command {
+    pub fn parse(buf: &[u8; COMMAND_LEN]) -> Result<Command> {
+        let param = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]);
+        let cmd = match buf[0] {
+            0x01 => Command::SetFrequency(param),
+            0x02 => Command::SetSampleRate(param),
+            0x03 => Command::SetGainMode(param != 0),
+            0x04 => Command::SetGain(param as i32),
+            0x05 => Command::SetFreqCorrection(param as i32),
+            0x06 => Command::SetIfGain {
+                stage: (param >> 16) as u16,
+                gain: param as u16 as i16,
+            },
+            0x07 => Command::SetTestMode(param != 0),
+            0x08 => Command::SetAgcMode(param != 0),
+            0x09 => Command::SetDirectSampling(param),
+            0x0a => Command::SetOffsetTuning(param != 0),
+            0x0b => Command::SetRtlXtal(param),
+            0x0c => Command::SetTunerXtal(param),
+            0x0d => Command::SetGainByIndex(param),
+            0x0e => Command::SetBiasTee(param != 0),
+            cmd => return Err(RtlsdrError::UnknownCommand(cmd)),
+        };
+        Ok(cmd)
+    }
+
+    pub fn to_bytes(&self) -> [u8; COMMAND_LEN] {
+        let (cmd, param) = match *self {
+            Command::SetFrequency(freq) => (0x01, freq),
+            Command::SetSampleRate(rate) => (0x02, rate),
+            Command::SetGainMode(manual) => (0x03, manual as u32),
+            Command::SetGain(gain) => (0x04, gain as u32),
+            Command::SetFreqCorrection(ppm) => (0x05, ppm as u32),
+            Command::SetIfGain { stage, gain } => (0x06, (stage as u32) << 16 | gain as u16 as u32),
+            Command::SetTestMode(on) => (0x07, on as u32),
+            Command::SetAgcMode(on) => (0x08, on as u32),
+            Command::SetDirectSampling(mode) => (0x09, mode),
+            Command::SetOffsetTuning(on) => (0x0a, on as u32),
+            Command::SetRtlXtal(freq) => (0x0b, freq),
+            Command::SetTunerXtal(freq) => (0x0c, freq),
+            Command::SetGainByIndex(index) => (0x0d, index),
+            Command::SetBiasTee(on) => (0x0e, on as u32),
+        };
+        let mut buf = [cmd; COMMAND_LEN];
+        buf[1..].copy_from_slice(&param.to_be_bytes());
+        buf
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Bytes per read from the device
+    pub buf_len: usize,
+    /// Buffers queued for a slow client before new samples are dropped
+    pub max_queued: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            buf_len: DEFAULT_BUF_LENGTH,
+            max_queued: DEFAULT_MAX_QUEUED,
+        }
+    }
+}
+
+/// Serve clients one after another until accepting fails or the device goes away.
+/// Settings made by one client stay in effect for the next.
+pub fn serve(sdr: &RtlSdr, listener: &TcpListener, config: &ServerConfig) -> Result<()> {
+    loop {
+        let (stream, addr) = listener.accept()?;
+        info!("Client connected from {}", addr);
+        match serve_client(sdr, stream, config) {
+            Ok(()) => info!("Client {} disconnected", addr),
+            Err(RtlsdrError::DeviceDisconnected) => return Err(RtlsdrError::DeviceDisconnected),
+            Err(e) => error!("Client {} failed: {}", addr, e),
+        }
+    }
+}
+
+/// Stream samples to a single client until it disconnects
+pub fn serve_client(sdr: &RtlSdr, stream: TcpStream, config: &ServerConfig) -> Result<()> {
+    let info = DongleInfo {
+        tuner_type: sdr.get_tuner_type() as u32,
+        gain_count: sdr.get_tuner_gains()?.len() as u32,
+    };
+    (&stream).write_all(&info.to_bytes())?;
+
+    let (cmd_tx, cmd_rx) = mpsc::channel();
+    let reader_stream = stream.try_clone()?;
+    let reader = thread::spawn(move || read_commands(reader_stream, cmd_tx));
+
+    let (buf_tx, buf_rx) = mpsc::sync_channel(config.max_queued);
+    let writer_stream = stream.try_clone()?;
+    let writer = thread::spawn(move || write_samples(writer_stream, buf_rx));
+
+    let result = stream_samples(sdr, config, &cmd_rx, &buf_tx);
+
+    // Unblock both threads before waiting for them
+    stream.shutdown(Shutdown::Both).ok();
+    drop(buf_tx);
+    reader.join().ok();
+    writer.join().ok();
+    result
+}
+
+fn stream_samples(
+    sdr: &RtlSdr,
+    config: &ServerConfig,
+    cmd_rx: &Receiver<Command>,
+    buf_tx: &SyncSender<Vec<u8>>,
+) -> Result<()> {
+    let mut state = ClientState::default();
+    // Buffers dropped since the client last kept up, so a slow client logs once per stall
+    let mut dropped = 0;
+    sdr.reset_buffer()?;
+    loop {
+        loop {
+            match cmd_rx.try_recv() {
+                Ok(cmd) => match state.apply(sdr, cmd) {
+                    Err(RtlsdrError::DeviceDisconnected) => {
+                        return Err(RtlsdrError::DeviceDisconnected)
+                    }
+                    Err(e) => error!("{:?} failed: {}", cmd, e),
+                    Ok(()) => (),
+                },
+                Err(TryRecvError::Empty) => break,
+                // Reader exits when the client goes away
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        let mut buf = vec![0; config.buf_len];
+        let n = sdr.read_sync(&mut buf)?;
+        buf.truncate(n);
+        match buf_tx.try_send(buf) {
+            Ok(()) if dropped > 0 => {
+                info!("Client caught up, dropped {} buffers", dropped);
+                dropped = 0;
+            }
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                if dropped == 0 {
+                    info!("Client too slow, dropping samples");
+                }
+                dropped += 1;
+            }
+            Err(TrySendError::Disconnected(_)) => return Ok(()),
+        }
+    }
+}
+
+fn read_commands(mut stream: TcpStream, tx: mpsc::Sender<Command>) {
+    let mut buf = [0; COMMAND_LEN];
+    while stream.read_exact(&mut buf).is_ok() {
+        match Command::parse(&buf) {
+            Ok(cmd) => {
+                if tx.send(cmd).is_err() {
+                    break;
+                }
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+fn write_samples(mut stream: TcpStream, rx: Receiver<Vec<u8>>) {
+    for buf in rx {
+        if stream.write_all(&buf).is_err() {
+            break;
+        }
+    }
+}
+
+/// Settings the rtl_tcp protocol changes piecemeal, e.g. the gain mode separately from the gain
+#[derive(Debug, Default)]
+struct ClientState {
+    manual_gain: bool,
+    gain: i32,
+}
+
+impl ClientState {
+    fn apply(&mut self, sdr: &RtlSdr, cmd: Command) -> Result<()> {
+        info!("{:?}", cmd);
+        match cmd {
+            Command::SetFrequency(freq) => sdr.set_center_freq(freq),
+            Command::SetSampleRate(rate) => sdr.set_sample_rate(rate),
+            Command::SetGainMode(manual) => {
+                self.manual_gain = manual;
+                if manual {
+                    sdr.set_tuner_gain(TunerGain::Manual(self.gain))
+                } else {
+                    sdr.set_tuner_gain(TunerGain::Auto)
+                }
+            }
+            Command::SetGain(gain) => {
+                self.gain = gain;
+                self.manual_gain = true;
+                sdr.set_tuner_gain(TunerGain::Manual(gain))
+            }
+            Command::SetFreqCorrection(ppm) => sdr.set_freq_correction(ppm),
+            Command::SetIfGain { stage, gain } => sdr.set_tuner_if_gain(stage as i32, gain as i32),
+            Command::SetTestMode(on) => sdr.set_testmode(on),
+            Command::SetAgcMode(on) => sdr.set_agc_mode(on),
+            Command::SetDirectSampling(mode) => sdr.set_direct_sampling(match mode {
+                0 => DirectSampleMode::Off,
+                1 => DirectSampleMode::On,
+                _ => DirectSampleMode::OnSwap,
+            }),
+            Command::SetOffsetTuning(on) => sdr.set_offset_tuning(on),
+            Command::SetRtlXtal(freq) => sdr.set_xtal_freq(freq, 0),
+            Command::SetTunerXtal(freq) => sdr.set_xtal_freq(0, freq),
+            Command::SetGainByIndex(index) => {
+                let gains = sdr.get_tuner_gains()?;
+                match gains.get(index as usize) {
+                    Some(gain) => self.apply(sdr, Command::SetGain(*gain)),
+                    None => Err(RtlsdrError::GainIndexOutOfRange {
+                        index,
+                        count: gains.len(),
+                    }),
+                }
+            }
+            Command::SetBiasTee(on) => sdr.set_bias_tee(on),
+        }
+    }
+}
+
+/// Connection to a remote rtl_tcp server. Implements `SdrDevice`, so it can stand in for a
+/// local `RtlSdr`.
+///
+/// The protocol has no way to read settings back, so getters report the last value set
+/// through this client.
+#[derive(Debug)]
+pub struct RtlTcpClient {
+    stream: TcpStream,
+    info: DongleInfo,
+    settings: Mutex<RemoteSettings>,
+}
+
+#[derive(Debug, Default)]
+struct RemoteSettings {
+    freq: u32,
+    rate: u32,
+    ppm: i32,
+}
+
+impl RtlTcpClient {
+    /// Connect and read the dongle info header
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<RtlTcpClient> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut header = [0; DONGLE_INFO_LEN];
+        stream.read_exact(&mut header)?;
+        let info = DongleInfo::parse(&header)?;
+        let gains = tuners::tuner_gains(info.tuner_type.into());
+        if gains.len() != info.gain_count as usize {
+            warn!(
+                "Server reports {} gains for {:?}, expected {}",
+                info.gain_count,
+                TunerType::from(info.tuner_type),
+                gains.len()
+            );
+        }
+        stream.set_nodelay(true).ok();
+        Ok(RtlTcpClient {
+            stream,
+            info,
+            settings: Mutex::new(RemoteSettings::default()),
+        })
+    }
+
+    pub fn dongle_info(&self) -> DongleInfo {
+        self.info
+    }
+
+    pub fn send(&self, cmd: Command) -> Result<()> {
+        (&self.stream)
+            .write_all(&cmd.to_bytes())
+            .map_err(stream_error)
+    }
+}
+
+impl SdrDevice for RtlTcpClient {
+    fn get_tuner_type(&self) -> TunerType {
+        self.info.tuner_type.into()
+    }
+    fn get_tuner_gains(&self) -> Result<Vec<i32>> {
+        Ok(tuners::tuner_gains(self.get_tuner_type()).to_vec())
+    }
+    fn set_tuner_gain(&self, gain: TunerGain) -> Result<()> {
+        match gain {
+            TunerGain::Auto => self.send(Command::SetGainMode(false)),
+            TunerGain::Manual(gain) => {
+                self.send(Command::SetGainMode(true))?;
+                self.send(Command::SetGain(gain))
+            }
+        }
+    }
+    fn get_center_freq(&self) -> u32 {
+        self.settings.lock().freq
+    }
+    fn set_center_freq(&self, freq: u32) -> Result<()> {
+        self.send(Command::SetFrequency(freq))?;
+        self.settings.lock().freq = freq;
+        Ok(())
+    }
+    fn get_sample_rate(&self) -> u32 {
+        self.settings.lock().rate
+    }
+    fn set_sample_rate(&self, rate: u32) -> Result<()> {
+        self.send(Command::SetSampleRate(rate))?;
+        self.settings.lock().rate = rate;
+        Ok(())
+    }
+    fn get_freq_correction(&self) -> i32 {
+        self.settings.lock().ppm
+    }
+    fn set_freq_correction(&self, ppm: i32) -> Result<()> {
+        self.send(Command::SetFreqCorrection(ppm))?;
+        self.settings.lock().ppm = ppm;
+        Ok(())
+    }
+    fn set_bias_tee(&self, on: bool) -> Result<()> {
+        self.send(Command::SetBiasTee(on))
+    }
+    fn set_direct_sampling(&self, mode: DirectSampleMode) -> Result<()> {
+        let mode = match mode {
+            DirectSampleMode::Off => 0,
+            DirectSampleMode::On => 1,
+            DirectSampleMode::OnSwap => 2,
+        };
+        self.send(Command::SetDirectSampling(mode))
+    }
+    /// The server streams continuously, so there is nothing to reset
+    fn reset_buffer(&self) -> Result<()> {
+        Ok(())
+    }
+    fn read_sync(&self, buf: &mut [u8]) -> Result<usize> {
+        (&self.stream).read_exact(buf).map_err(stream_error)?;
+        Ok(buf.len())
+    }
+}
+
+/// A server that goes away mid-stream is the remote equivalent of an unplugged device
+fn stream_error(e: std::io::Error) -> RtlsdrError {
+    match e.kind() {
+        ErrorKind::UnexpectedEof
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe => RtlsdrError::DeviceDisconnected,
+        _ => RtlsdrError::Io(e),
+    }
+}
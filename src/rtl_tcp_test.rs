@@ -0,0 +1,126 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use crate::error::RtlsdrError;
+use crate::rtl_tcp::{serve, Command, DongleInfo, RtlTcpClient, ServerConfig, DONGLE_INFO_LEN};
+use crate::tuners;
+use crate::virtual_device::{VirtualConfig, VirtualDevice};
+use crate::{RtlSdr, SdrDevice, TunerGain, TunerType};
+
+#[test]
+fn test_command_round_trip() {
+    let cmds = [
+        Command::SetFrequency(100_000_000),
+        Command::SetGain(-10),
+        Command::SetIfGain {
+            stage: 3,
+            gain: -40,
+        },
+        Command::SetBiasTee(true),
+    ];
+    for cmd in cmds {
+        assert_eq!(Command::parse(&cmd.to_bytes()).unwrap(), cmd);
+    }
+    assert_eq!(
+        Command::SetFrequency(100_000_000).to_bytes(),
+        [0x01, 0x05, 0xf5, 0xe1, 0x00]
+    );
+    assert!(matches!(
+        Command::parse(&[0x42, 0, 0, 0, 0]),
+        Err(RtlsdrError::UnknownCommand(0x42))
+    ));
+}
+
+#[test]
+fn test_tuner_gains() {
+    assert_eq!(tuners::tuner_gains(TunerType::R828D).len(), 29);
+    assert!(tuners::tuner_gains(TunerType::Unknown).is_empty());
+}
+
+fn start_server(dev: VirtualDevice) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let sdr = RtlSdr::open_transport(Box::new(dev)).unwrap();
+        sdr.set_sample_rate(1_024_000).unwrap();
+        let config = ServerConfig {
+            buf_len: 16384,
+            max_queued: 4,
+        };
+        serve(&sdr, &listener, &config).unwrap();
+    });
+    port
+}
+
+fn connect(port: u16) -> (TcpStream, DongleInfo) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut header = [0; DONGLE_INFO_LEN];
+    stream.read_exact(&mut header).unwrap();
+    (stream, DongleInfo::parse(&header).unwrap())
+}
+
+#[test]
+fn test_sequential_clients() {
+    let dev = VirtualDevice::new(VirtualConfig::default());
+    let port = start_server(dev.clone());
+
+    let (mut stream, info) = connect(port);
+    assert_eq!(info.tuner_type, TunerType::R820T as u32);
+    assert_eq!(info.gain_count, 29);
+    stream
+        .write_all(&Command::SetFrequency(144_800_000).to_bytes())
+        .unwrap();
+    stream
+        .write_all(&Command::SetTestMode(true).to_bytes())
+        .unwrap();
+    // Buffers queued before the commands were applied still hold noise
+    let mut buf = vec![0; 16384];
+    let is_counter = |buf: &[u8]| buf.windows(2).all(|w| w[1] == w[0].wrapping_add(1));
+    let mut reads = 0;
+    while !is_counter(&buf) {
+        assert!(reads < 100, "test mode never took effect");
+        stream.read_exact(&mut buf).unwrap();
+        reads += 1;
+    }
+    assert!((dev.tuned_freq() - 144_800_000.0).abs() < 1000.0);
+    drop(stream);
+
+    // The next client gets a fresh header and a working stream
+    let (mut stream, info) = connect(port);
+    assert_eq!(info.tuner_type, TunerType::R820T as u32);
+    stream.read_exact(&mut buf).unwrap();
+}
+
+// Same code path for local and remote devices
+fn tune(sdr: &dyn SdrDevice, freq: u32) {
+    sdr.set_sample_rate(1_024_000).unwrap();
+    sdr.set_center_freq(freq).unwrap();
+    sdr.set_tuner_gain(TunerGain::Manual(sdr.get_tuner_gains().unwrap()[10]))
+        .unwrap();
+}
+
+#[test]
+fn test_client() {
+    let dev = VirtualDevice::new(VirtualConfig::default());
+    let port = start_server(dev.clone());
+
+    let client = RtlTcpClient::connect(("127.0.0.1", port)).unwrap();
+    assert_eq!(client.get_tuner_type(), TunerType::R820T);
+    let local = RtlSdr::open(crate::DeviceSelector::Virtual(Box::default())).unwrap();
+    assert_eq!(
+        client.get_tuner_gains().unwrap(),
+        local.get_tuner_gains().unwrap()
+    );
+
+    tune(&client, 433_920_000);
+    assert_eq!(client.get_center_freq(), 433_920_000);
+    let mut buf = vec![0; 16384];
+    let mut reads = 0;
+    while (dev.tuned_freq() - 433_920_000.0).abs() > 1000.0 {
+        assert!(reads < 100, "frequency change never reached the device");
+        assert_eq!(client.read_sync(&mut buf).unwrap(), buf.len());
+        reads += 1;
+    }
+    tune(&local, 433_920_000);
+}
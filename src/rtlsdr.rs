@@ -1,22 +1,37 @@
-use super::{DirectSampleMode, TunerGain};
+use super::{
+    BufferInfo, DirectSampleMode, FreqTranslation, GainStage, StageGain, TunerGain, TunerType,
+    XtalCap, XtalCapValue, XtalFreqs, DEFAULT_BUF_LENGTH, DEFAULT_BUF_NUMBER, SAMPLE_RATE_RANGES,
+};
 use crate::device::{
-    Device, BLOCK_SYS, BLOCK_USB, DEMOD_CTL, DEMOD_CTL_1, EEPROM_SIZE, GPD, GPO, GPOE, USB_EPA_CTL,
-    USB_EPA_MAXPKT, USB_SYSCTL,
+    Device, BLOCK_SYS, BLOCK_USB, DEMOD_CTL, DEMOD_CTL_1, EEPROM_SIZE, USB_EPA_CTL, USB_EPA_MAXPKT,
+    USB_SYSCTL,
+};
+use crate::eeprom::EepromConfig;
+use crate::error::RtlsdrError::{
+    FrequencyOutOfRange, InvalidBufferLength, InvalidFirCoefficient, InvalidFreqCorrection,
+    InvalidSampleRate, InvalidXtalFreq, OffsetTuningInDirectSampling, OffsetTuningUnsupported,
+    PllNotLocked, TunerNotFound, UnsupportedTuner,
 };
-use crate::error::Result;
-use crate::error::RtlsdrError::RtlsdrErr;
-use crate::tuners::r820t::{R820T, R82XX_IF_FREQ, TUNER_ID};
-use crate::tuners::{NoTuner, Tuner, KNOWN_TUNERS};
-use log::{error, info};
+use crate::error::{EepromError, Result, RtlsdrError};
+use crate::tuners::e4k::{self, E4k};
+use crate::tuners::fc001x::{Fc001x, FC0012_TUNER_ID, FC0013_TUNER_ID};
+use crate::tuners::fc2580::{self, Fc2580, FC2580_XTAL_FREQ};
+use crate::tuners::r820t::{R820T, R828D_TUNER_ID, R828D_XTAL_FREQ, R82XX_IF_FREQ, TUNER_ID};
+use crate::tuners::{NoTuner, Tuner, TunerInfo, KNOWN_TUNERS};
+use log::{error, info, warn};
 use parking_lot::ReentrantMutex;
 use std::cell::RefCell;
-use std::ops::Deref;
+use std::ops::{Deref, RangeInclusive};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 const INTERFACE_ID: u8 = 0;
 
 const DEF_RTL_XTAL_FREQ: u32 = 28_800_000;
 const MIN_RTL_XTAL_FREQ: u32 = DEF_RTL_XTAL_FREQ - 1000;
 const MAX_RTL_XTAL_FREQ: u32 = DEF_RTL_XTAL_FREQ + 1000;
+// Limit of the demod's 14 bit sample rate correction, 2^13 / 2^24 * 1e6
+const MAX_FREQ_CORRECTION: f64 = 488.0;
 
 pub(crate) const FIR_LEN: usize = 16;
 const DEFAULT_FIR: &[i32; FIR_LEN] = &[
@@ -28,22 +43,30 @@ const DEFAULT_FIR: &[i32; FIR_LEN] = &[
 pub struct RtlSdr {
     handle: Device,
     i: ReentrantMutex<RefCell<Inner>>,
+    async_cancel: Arc<AtomicBool>,
 }
 
 #[derive(Debug)]
 struct Inner {
     tuner: Box<dyn Tuner>,
-    freq: u32, // Hz
+    freq: u32, // Hz, at the dongle input
+    rf_freq: u64,
+    translation: FreqTranslation,
     rate: u32, // Hz
     bw: u32,
     direct_sampling: DirectSampleMode,
     xtal: u32,
     tuner_xtal: u32,
-    ppm_correction: u32,
-    offset_freq: u32,
-    corr: i32, // PPM
+    blog_v4: bool,
+    offset_freq: u32, // Hz, tuner LO below the center frequency when offset tuning
+    // Offset tuning switches the bias tee on R82xx tuners, the RTL-SDR Blog behaviour
+    offset_bt: bool,
+    if_freq: u32, // Hz, as requested from the DDC
+    corr: f64,    // PPM
     force_bt: bool,
     force_ds: bool,
+    // Fail retunes when the tuner PLL doesn't lock
+    require_lock: bool,
     _fir: [i32; FIR_LEN],
 }
 
@@ -54,18 +77,24 @@ impl RtlSdr {
             i: ReentrantMutex::new(RefCell::new(Inner {
                 tuner: Box::new(NoTuner {}),
                 freq: 0,
+                rf_freq: 0,
+                translation: FreqTranslation::default(),
                 rate: 0,
                 bw: 0,
-                ppm_correction: 0,
                 xtal: DEF_RTL_XTAL_FREQ,
                 tuner_xtal: DEF_RTL_XTAL_FREQ,
+                blog_v4: false,
                 direct_sampling: DirectSampleMode::Off,
                 offset_freq: 0,
-                corr: 0,
+                offset_bt: cfg!(feature = "rtl_sdr_blog"),
+                if_freq: 0,
+                corr: 0.0,
                 force_bt: false,
                 force_ds: false,
+                require_lock: false,
                 _fir: *DEFAULT_FIR,
             })),
+            async_cancel: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -77,54 +106,69 @@ impl RtlSdr {
 
         let inner = self.i.lock();
 
+        let eeprom = match self.get_eeprom_config() {
+            Ok(config) => {
+                // Hack to force the Bias T to always be on if we set the IR-Endpoint bit in the EEPROM to 0. Default on EEPROM is 1.
+                inner.deref().borrow_mut().force_bt = !config.enable_ir;
+                // Hack to force direct sampling mode to always be on if we set the remote-enabled bit in the EEPROM to 1. Default on EEPROM is 0.
+                inner.deref().borrow_mut().force_ds = config.remote_wakeup;
+                Some(config)
+            }
+            // Not every dongle has a valid EEPROM, that shouldn't stop it from working
+            Err(e) => {
+                warn!("Unable to read EEPROM config: {}", e);
+                None
+            }
+        };
+        let blog_v4 = eeprom.is_some_and(|config| is_blog_v4(&config));
+
+        inner.deref().borrow_mut().blog_v4 = blog_v4;
         inner.deref().borrow_mut().tuner = {
             let tuner_id = match self.search_tuner() {
-                Some(tid) => {
-                    info!("Got tuner ID {}", tid);
-                    tid
-                }
-                None => {
-                    panic!("Failed to find tuner, aborting");
+                Some(info) => {
+                    info!("Got tuner ID {}", info.id);
+                    info.id
                 }
+                None => return Err(TunerNotFound),
             };
             match tuner_id {
+                e4k::TUNER_ID => Box::new(E4k::new(&mut self.handle)),
+                FC0012_TUNER_ID => Box::new(Fc001x::new_fc0012(&mut self.handle)),
+                FC0013_TUNER_ID => Box::new(Fc001x::new_fc0013(&mut self.handle)),
+                fc2580::TUNER_ID => Box::new(Fc2580::new(&mut self.handle)),
                 TUNER_ID => Box::new(R820T::new(&mut self.handle)),
-                _ => panic!("Unable to find recognized tuner"),
+                R828D_TUNER_ID => {
+                    if blog_v4 {
+                        info!("RTL-SDR Blog V4 detected");
+                    }
+                    Box::new(R820T::new_r828d(&mut self.handle, blog_v4))
+                }
+                id => return Err(UnsupportedTuner(id)),
             }
         };
-        // Use the RTL clock value by default
         let x = inner.deref().borrow().xtal;
-        inner.deref().borrow_mut().tuner_xtal = x;
+        inner.deref().borrow_mut().tuner_xtal =
+            default_tuner_xtal_freq(self.get_tuner_type(), blog_v4).unwrap_or(x);
         let xf = self.get_tuner_xtal_freq();
         inner.deref().borrow_mut().tuner.set_xtal_freq(xf)?;
 
-        // disable Zero-IF mode
-        self.handle.demod_write_reg(1, 0xb1, 0x1a, 1)?;
+        if self.is_r82xx() {
+            // disable Zero-IF mode
+            self.handle.demod_write_reg(1, 0xb1, 0x1a, 1)?;
 
-        // only enable In-phase ADC input
-        self.handle.demod_write_reg(0, 0x08, 0x4d, 1)?;
+            // only enable In-phase ADC input
+            self.handle.demod_write_reg(0, 0x08, 0x4d, 1)?;
 
-        // the R82XX use 3.57 MHz IF for the DVB-T 6 MHz mode, and
-        // 4.57 MHz for the 8 MHz mode
-        self.set_if_freq(R82XX_IF_FREQ)?;
+            // the R82XX use 3.57 MHz IF for the DVB-T 6 MHz mode, and
+            // 4.57 MHz for the 8 MHz mode
+            self.set_if_freq(R82XX_IF_FREQ)?;
 
-        // enable spectrum inversion
-        self.handle.demod_write_reg(1, 0x15, 0x01, 1)?;
-
-        // Hack to force the Bias T to always be on if we set the IR-Endpoint bit in the EEPROM to 0. Default on EEPROM is 1.
-        let buf: [u8; EEPROM_SIZE] = [0; EEPROM_SIZE];
-        self.handle.read_eeprom(&buf, 0, EEPROM_SIZE)?;
-        if buf[7] & 0x02 != 0 {
-            inner.deref().borrow_mut().force_bt = false;
-        } else {
-            inner.deref().borrow_mut().force_bt = true;
-        }
-        // Hack to force direct sampling mode to always be on if we set the remote-enabled bit in the EEPROM to 1. Default on EEPROM is 0.
-        if buf[7] & 0x01 != 0 {
-            inner.deref().borrow_mut().force_ds = true;
+            // enable spectrum inversion
+            self.set_spectrum_inversion()?;
         } else {
-            inner.deref().borrow_mut().force_ds = false;
+            self.set_zero_if()?;
         }
+
         // TODO: if(force_ds){tuner_type = TUNER_UNKNOWN}
         info!("Init tuner");
         inner.deref().borrow_mut().tuner.init(&self.handle)?;
@@ -155,7 +199,86 @@ impl RtlSdr {
         Ok(())
     }
 
-    // TODO: set_bias_tee
+    pub fn get_tuner_type(&self) -> TunerType {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.tuner_type();
+        r
+    }
+
+    fn is_r82xx(&self) -> bool {
+        matches!(self.get_tuner_type(), TunerType::R820T | TunerType::R828D)
+    }
+
+    pub fn set_tuner_if_gain(&self, stage: i32, gain: i32) -> Result<()> {
+        let inner = self.i.lock();
+        self.set_i2c_repeater(true)?;
+        inner
+            .deref()
+            .borrow_mut()
+            .tuner
+            .set_if_gain(&self.handle, stage, gain)?;
+        self.set_i2c_repeater(false)?;
+        Ok(())
+    }
+
+    pub fn get_tuner_stage_gains(&self, stage: GainStage) -> Result<Vec<i32>> {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.get_stage_gains(stage);
+        r
+    }
+
+    pub fn set_tuner_stage_gain(&self, stage: GainStage, gain: StageGain) -> Result<()> {
+        let inner = self.i.lock();
+        self.set_i2c_repeater(true)?;
+        let r = inner
+            .deref()
+            .borrow_mut()
+            .tuner
+            .set_stage_gain(&self.handle, stage, gain);
+        self.set_i2c_repeater(false)?;
+        r
+    }
+
+    pub fn get_tuner_filter_cal_code(&self) -> Option<u8> {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.get_filter_cal_code();
+        r
+    }
+
+    pub fn get_xtal_cap(&self) -> Option<XtalCapValue> {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.get_xtal_cap();
+        r
+    }
+
+    pub fn set_xtal_cap(&self, cap: XtalCap) -> Result<()> {
+        let inner = self.i.lock();
+        inner.deref().borrow_mut().tuner.set_xtal_cap(cap)?;
+        let calibrated = if cap == XtalCap::Calibrate {
+            self.set_i2c_repeater(true)?;
+            let r = inner
+                .deref()
+                .borrow_mut()
+                .tuner
+                .calibrate_xtal_cap(&self.handle);
+            self.set_i2c_repeater(false)?;
+            r
+        } else {
+            Ok(())
+        };
+        let freq = inner.deref().borrow().freq;
+        if freq != 0 {
+            // The capacitor is applied on retuning
+            self.set_center_freq(freq)?;
+        }
+        calibrated
+    }
+
+    pub fn set_agc_mode(&self, on: bool) -> Result<()> {
+        let val = if on { 0x25 } else { 0x05 };
+        self.handle.demod_write_reg(0, 0x19, val, 1)?;
+        Ok(())
+    }
 
     pub fn reset_buffer(&self) -> Result<()> {
         self.handle.write_reg(BLOCK_USB, USB_EPA_CTL, 0x1002, 2)?;
@@ -169,6 +292,74 @@ impl RtlSdr {
         r
     }
 
+    /// RF frequency of the last `set_rf_freq`
+    pub fn get_rf_freq(&self) -> u64 {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().rf_freq;
+        r
+    }
+
+    /// Tune the dongle to the translation of an RF frequency
+    pub fn set_rf_freq(&self, rf_freq: u64) -> Result<()> {
+        let inner = self.i.lock();
+        let translation = inner.deref().borrow().translation;
+        let to_rf = |freq: u64| {
+            if translation.inverted {
+                translation.lo_offset as i128 - freq as i128
+            } else {
+                freq as i128 - translation.lo_offset as i128
+            }
+        };
+        let Some(freq) = translation.dongle_freq(rf_freq) else {
+            let e = inner.deref().borrow().tuner.out_of_range(0);
+            return Err(out_of_range_for(e, rf_freq, to_rf));
+        };
+        self.set_center_freq(freq)
+            .map_err(|e| out_of_range_for(e, rf_freq, to_rf))?;
+        inner.deref().borrow_mut().rf_freq = rf_freq;
+        Ok(())
+    }
+
+    pub fn get_freq_translation(&self) -> FreqTranslation {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().translation;
+        r
+    }
+
+    pub fn set_freq_translation(&self, translation: FreqTranslation) -> Result<()> {
+        let inner = self.i.lock();
+        let (old, freq, rf_freq) = {
+            let inner_ref = inner.deref().borrow();
+            (inner_ref.translation, inner_ref.freq, inner_ref.rf_freq)
+        };
+        inner.deref().borrow_mut().translation = translation;
+        self.set_spectrum_inversion()?;
+        if freq != 0 {
+            // Keep the RF frequency
+            if let Err(e) = self.set_rf_freq(rf_freq) {
+                inner.deref().borrow_mut().translation = old;
+                self.set_spectrum_inversion()?;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// R82xx tuners mirror the spectrum on their low IF, and an inverting converter mirrors
+    /// it once more. The demod undoes whatever is left.
+    fn set_spectrum_inversion(&self) -> Result<()> {
+        let inner = self.i.lock();
+        let (direct_sampling, inverted) = {
+            let inner_ref = inner.deref().borrow();
+            (inner_ref.direct_sampling, inner_ref.translation.inverted)
+        };
+        let tuner_inverts = self.is_r82xx() && matches!(direct_sampling, DirectSampleMode::Off);
+        self.handle
+            .demod_write_reg(1, 0x15, u16::from(tuner_inverts != inverted), 1)?;
+        Ok(())
+    }
+
+    /// Tune the dongle, i.e. after the frequency translation
     pub fn set_center_freq(&self, freq: u32) -> Result<()> {
         let inner = self.i.lock();
         if !matches!(
@@ -177,26 +368,64 @@ impl RtlSdr {
         ) {
             self.set_if_freq(freq)?;
         } else {
+            let offset = inner.deref().borrow().offset_freq;
+            // Errors list the ranges of the center frequency rather than the tuner's
+            let to_center = |lo: u64| lo as i128 + offset as i128;
+            let Some(lo) = freq.checked_sub(offset) else {
+                let e = inner.deref().borrow().tuner.out_of_range(0);
+                return Err(out_of_range_for(e, freq as u64, to_center));
+            };
             self.set_i2c_repeater(true)?;
-            // TODO: figure out offset_freq, currently never set
-            let lo = inner.deref().borrow().offset_freq;
-            inner
-                .deref()
-                .borrow_mut()
-                .tuner
-                .set_freq(&self.handle, freq - lo)?;
+            let r = inner.deref().borrow_mut().tuner.set_freq(&self.handle, lo);
             self.set_i2c_repeater(false)?;
+            r.map_err(|e| out_of_range_for(e, freq as u64, to_center))?;
+            let (unlocked, old_freq) = {
+                let inner_ref = inner.deref().borrow();
+                (
+                    inner_ref.require_lock && inner_ref.tuner.pll_locked() == Some(false),
+                    inner_ref.freq,
+                )
+            };
+            if unlocked {
+                // Put the tuner back where it was, lock or not
+                if let Some(old_lo) = old_freq.checked_sub(offset).filter(|_| old_freq != 0) {
+                    self.set_i2c_repeater(true)?;
+                    let r = inner
+                        .deref()
+                        .borrow_mut()
+                        .tuner
+                        .set_freq(&self.handle, old_lo);
+                    self.set_i2c_repeater(false)?;
+                    r?;
+                }
+                return Err(PllNotLocked { freq: freq as u64 });
+            }
         }
         inner.deref().borrow_mut().freq = freq;
         Ok(())
     }
 
+    /// Whether the tuner PLL locked on the last retune, `None` if the tuner can't tell
+    pub fn get_pll_locked(&self) -> Option<bool> {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.pll_locked();
+        r
+    }
+
+    pub fn set_require_pll_lock(&self, on: bool) {
+        let inner = self.i.lock();
+        inner.deref().borrow_mut().require_lock = on;
+    }
+
+    pub fn get_tuner_freq_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.freq_ranges();
+        r
+    }
+
     pub fn set_if_freq(&self, freq: u32) -> Result<()> {
-        // Get corrected clock value - start with default
-        let rtl_xtal: u32 = DEF_RTL_XTAL_FREQ;
-        // Apply PPM correction
-        let base = 1u32 << 22;
-        let if_freq: i32 = (freq as f64 * base as f64 / rtl_xtal as f64 * -1f64) as i32;
+        // The DDC runs from the corrected RTL clock
+        let if_freq = if_freq_word(freq, self.get_xtal_freq());
 
         let tmp = ((if_freq >> 16) as u16) & 0x3f;
         self.handle.demod_write_reg(1, 0x19, tmp, 1)?;
@@ -204,32 +433,100 @@ impl RtlSdr {
         self.handle.demod_write_reg(1, 0x1a, tmp, 1)?;
         let tmp = if_freq as u16 & 0xff;
         self.handle.demod_write_reg(1, 0x1b, tmp, 1)?;
+        let inner = self.i.lock();
+        inner.deref().borrow_mut().if_freq = freq;
         Ok(())
     }
 
+    /// IF the DDC actually shifts by, after quantisation
+    pub fn get_if_freq(&self) -> f64 {
+        let inner = self.i.lock();
+        let if_freq = inner.deref().borrow().if_freq;
+        let rtl_xtal = self.get_xtal_freq();
+        if_freq_word_freq(if_freq_word(if_freq, rtl_xtal), rtl_xtal)
+    }
+
+    /// Actual tuner LO of the last retune, `None` in direct sampling mode or if the tuner
+    /// can't compute it
+    pub fn get_tuner_lo_freq(&self) -> Option<f64> {
+        let inner = self.i.lock();
+        let inner_ref = inner.deref().borrow();
+        if !matches!(inner_ref.direct_sampling, DirectSampleMode::Off) {
+            return None;
+        }
+        inner_ref.tuner.get_lo_freq().map(|lo| lo.actual)
+    }
+
+    /// Frequency that ends up at 0 Hz in the samples, minus the center frequency. Combines
+    /// the tuner PLL and DDC quantisation errors.
+    pub fn get_tuning_error(&self) -> Option<f64> {
+        let inner = self.i.lock();
+        let inner_ref = inner.deref().borrow();
+        let if_requested = inner_ref.if_freq;
+        let if_error = self.get_if_freq() - if_requested as f64;
+        let error = if !matches!(inner_ref.direct_sampling, DirectSampleMode::Off) {
+            // The DDC alone tunes
+            if_error
+        } else {
+            // The tuner mixes the center frequency to the IF, which the DDC removes. R82xx
+            // tuners put their LO above the RF, zero-IF tuners below it when offset tuning.
+            let lo = inner_ref.tuner.get_lo_freq()?;
+            let lo_error = lo.actual - lo.requested as f64;
+            if self.is_r82xx() {
+                lo_error - if_error
+            } else {
+                lo_error + if_error
+            }
+        };
+        // An inverting converter mirrors the error too
+        Some(if inner_ref.translation.inverted {
+            -error
+        } else {
+            error
+        })
+    }
+
     pub fn get_freq_correction(&self) -> i32 {
+        self.get_freq_correction_ppm().round() as i32
+    }
+
+    pub fn set_freq_correction(&self, ppm: i32) -> Result<()> {
+        self.set_freq_correction_ppm(ppm as f64)
+    }
+
+    pub fn get_freq_correction_ppm(&self) -> f64 {
         let inner = self.i.lock();
         let r = inner.deref().borrow().corr;
         r
     }
 
-    pub fn set_freq_correction(&self, ppm: i32) -> Result<()> {
+    pub fn set_freq_correction_ppm(&self, ppm: f64) -> Result<()> {
+        if !ppm.is_finite() || ppm.abs() > MAX_FREQ_CORRECTION {
+            return Err(InvalidFreqCorrection {
+                ppm,
+                supported: -MAX_FREQ_CORRECTION..=MAX_FREQ_CORRECTION,
+            });
+        }
         let inner = self.i.lock();
-        if inner.deref().borrow_mut().corr == ppm {
+        if inner.deref().borrow().corr == ppm {
             return Ok(());
         }
         inner.deref().borrow_mut().corr = ppm;
         self.set_sample_freq_correction(ppm)?;
 
         // Read corrected clock value into tuner
-        inner
-            .deref()
-            .borrow_mut()
-            .tuner
-            .set_xtal_freq(self.get_tuner_xtal_freq())?;
+        let tuner_xtal = self.get_tuner_xtal_freq();
+        inner.deref().borrow_mut().tuner.set_xtal_freq(tuner_xtal)?;
+
+        // Reprogram the DDC from the corrected RTL clock
+        let if_freq = inner.deref().borrow().if_freq;
+        self.set_if_freq(if_freq)?;
 
         // Retune to apply new correction value
-        self.set_center_freq(inner.deref().borrow().freq)?;
+        let freq = inner.deref().borrow().freq;
+        if freq != 0 {
+            self.set_center_freq(freq)?;
+        }
         Ok(())
     }
 
@@ -242,8 +539,11 @@ impl RtlSdr {
     pub fn set_sample_rate(&self, rate: u32) -> Result<()> {
         let inner = self.i.lock();
         // Check if rate is supported by the resampler
-        if rate <= 225_000 || rate > 3_200_000 || (rate > 300000 && rate <= 900000) {
-            return Err(RtlsdrErr(format!("Invalid sample rate: {rate} Hz")));
+        if !SAMPLE_RATE_RANGES.iter().any(|r| r.contains(&rate)) {
+            return Err(InvalidSampleRate {
+                rate,
+                supported: &SAMPLE_RATE_RANGES,
+            });
         }
 
         // Compute exact sample rate
@@ -279,8 +579,9 @@ impl RtlSdr {
             .tuner
             .set_bandwidth(&self.handle, val, r)?;
         self.set_i2c_repeater(false)?;
-        if inner.deref().borrow().tuner.get_info()?.id == TUNER_ID {
-            self.set_if_freq(inner.deref().borrow().tuner.get_if_freq()?)?;
+        if self.is_r82xx() {
+            let if_freq = inner.deref().borrow().tuner.get_if_freq()?;
+            self.set_if_freq(if_freq)?;
             let freq = inner.deref().borrow().freq;
             self.set_center_freq(freq)?;
         }
@@ -318,8 +619,7 @@ impl RtlSdr {
             .tuner
             .set_bandwidth(&self.handle, bw, r)?;
         self.set_i2c_repeater(false)?;
-        let is_tuner = inner.deref().borrow().tuner.get_info()?.id == TUNER_ID;
-        if is_tuner {
+        if self.is_r82xx() {
             let if_freq = inner.deref().borrow().tuner.get_if_freq()?;
             self.set_if_freq(if_freq)?;
             let freq = inner.deref().borrow().freq;
@@ -329,6 +629,24 @@ impl RtlSdr {
         Ok(())
     }
 
+    pub fn get_tuner_bandwidths(&self) -> Vec<u32> {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.get_bandwidths();
+        r
+    }
+
+    pub fn get_tuner_bandwidth(&self) -> u32 {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.get_bandwidth();
+        r
+    }
+
+    pub fn get_tuner_if_freq(&self) -> Result<u32> {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().tuner.get_if_freq();
+        r
+    }
+
     pub fn set_testmode(&self, on: bool) -> Result<()> {
         match on {
             true => {
@@ -355,9 +673,6 @@ impl RtlSdr {
                 // Disable Zero-IF mode
                 self.handle.demod_write_reg(1, 0xb1, 0x1a, 1)?;
 
-                // Disable spectrum inversion
-                self.handle.demod_write_reg(1, 0x15, 0x00, 1)?;
-
                 // Only enable in-phase ADC input
                 self.handle.demod_write_reg(0, 0x08, 0x4d, 1)?;
 
@@ -370,109 +685,269 @@ impl RtlSdr {
                     info!("Enabled direct sampling mode: ON");
                 }
                 inner.deref().borrow_mut().direct_sampling = mode;
+                // Disable spectrum inversion, unless a converter needs it
+                self.set_spectrum_inversion()?;
             }
             DirectSampleMode::Off => {
                 self.set_i2c_repeater(true)?;
                 inner.deref().borrow_mut().tuner.init(&self.handle)?;
                 self.set_i2c_repeater(false)?;
 
-                if inner.deref().borrow().tuner.get_info()?.id == TUNER_ID {
-                    // tuner init already does all this
-                    // self.set_if_freq(R82XX_IF_FREQ);
-                    // Enable spectrum inversion
-                    // handle.demod_write_reg(1, 0x15, 0x01, 1);
+                if self.is_r82xx() {
+                    // Direct sampling tuned the DDC to the center frequency
+                    self.set_if_freq(R82XX_IF_FREQ)?;
                 } else {
-                    self.set_if_freq(0)?;
-
-                    // Enable in-phase + Quadrature ADC input
-                    self.handle.demod_write_reg(0, 0x08, 0xcd, 1)?;
-
-                    // Enable Zero-IF mode
-                    self.handle.demod_write_reg(1, 0xb1, 0x1b, 1)?;
+                    self.set_zero_if()?;
+                    let offset = inner.deref().borrow().offset_freq;
+                    if offset != 0 {
+                        self.set_if_freq(offset)?;
+                    }
                 }
                 // opt_adc_iq = 0, default ADC_I/ADC_Q datapath
                 self.handle.demod_write_reg(0, 0x06, 0x80, 1)?;
                 info!("Disabled direct sampling mode");
                 inner.deref().borrow_mut().direct_sampling = DirectSampleMode::Off;
+                self.set_spectrum_inversion()?;
             }
         }
-        self.set_center_freq(inner.deref().borrow().freq)?;
+        let freq = inner.deref().borrow().freq;
+        self.set_center_freq(freq)?;
         Ok(())
     }
 
-    pub fn set_offset_tuning(&self, _enable: bool) -> Result<()> {
-        // RTL-SDR-BLOG Hack, enables us to turn on the bias tee by clicking on "offset tuning"
-        // in software that doesn't have specified bias tee support.
-        // Offset tuning is not used for R820T devices so it is no problem.
-        #[cfg(feature = "rtl_sdr_blog")]
-        self.set_gpio(0, enable)?;
+    pub fn set_offset_tuning(&self, on: bool) -> Result<()> {
+        let inner = self.i.lock();
+        if self.is_r82xx() {
+            // R82xx tuners already mix to a low IF, there's no DC spike to move away from
+            if inner.deref().borrow().offset_bt {
+                return self.set_bias_tee(on);
+            }
+            if on {
+                return Err(OffsetTuningUnsupported {
+                    tuner: self.get_tuner_type(),
+                });
+            }
+            return Ok(());
+        }
+        if !matches!(
+            inner.deref().borrow().direct_sampling,
+            DirectSampleMode::Off
+        ) {
+            return Err(OffsetTuningInDirectSampling);
+        }
+        let (rate, bw) = {
+            let inner_ref = inner.deref().borrow();
+            (inner_ref.rate, inner_ref.bw)
+        };
+        let offset = if on { offset_tuning_freq(rate) } else { 0 };
+        self.set_if_freq(offset)?;
+        inner.deref().borrow_mut().offset_freq = offset;
+
+        // Widen the filter to pass the signal at the offset
+        let bw = if on {
+            2 * offset
+        } else if bw > 0 {
+            bw
+        } else {
+            rate
+        };
+        self.set_i2c_repeater(true)?;
+        let r = inner
+            .deref()
+            .borrow_mut()
+            .tuner
+            .set_bandwidth(&self.handle, bw, rate);
+        self.set_i2c_repeater(false)?;
+        r?;
 
-        // TODO: implement the rest when we support tuners beyond R82xx
+        let freq = inner.deref().borrow().freq;
+        if freq > offset {
+            self.set_center_freq(freq)?;
+        }
         Ok(())
     }
 
+    pub fn get_offset_tuning(&self) -> bool {
+        let inner = self.i.lock();
+        let r = inner.deref().borrow().offset_freq != 0;
+        r
+    }
+
+    /// Have `set_offset_tuning` switch the bias tee on R82xx tuners, like the RTL-SDR Blog
+    /// drivers do for software without bias tee support
+    pub fn set_offset_tuning_bias_tee(&self, on: bool) {
+        let inner = self.i.lock();
+        inner.deref().borrow_mut().offset_bt = on;
+    }
+
     pub fn set_bias_tee(&self, on: bool) -> Result<()> {
         self.set_gpio(0, on)
     }
 
-    #[allow(dead_code)]
+    /// RTL clock after frequency correction
     pub fn get_xtal_freq(&self) -> u32 {
         let inner = self.i.lock();
-        let r = (inner.deref().borrow().xtal as f32
-            * (1.0 + inner.deref().borrow().ppm_correction as f32 / 1e6)) as u32;
-        r
+        let inner_ref = inner.deref().borrow();
+        apply_ppm(inner_ref.xtal, inner_ref.corr)
     }
 
+    /// Tuner PLL reference after frequency correction
     pub fn get_tuner_xtal_freq(&self) -> u32 {
         let inner = self.i.lock();
-        let r = (inner.deref().borrow().tuner_xtal as f32
-            * (1.0 + inner.deref().borrow().ppm_correction as f32 / 1e6)) as u32;
-        r
+        let inner_ref = inner.deref().borrow();
+        apply_ppm(inner_ref.tuner_xtal, inner_ref.corr)
     }
 
-    #[allow(dead_code)]
-    pub fn set_xtal_freq(&self, rtl_freq: u32, tuner_freq: u32) -> Result<()> {
+    /// Crystal frequencies as configured, before frequency correction
+    pub fn get_xtal_freqs(&self) -> XtalFreqs {
         let inner = self.i.lock();
+        let inner_ref = inner.deref().borrow();
+        XtalFreqs {
+            rtl: inner_ref.xtal,
+            tuner: inner_ref.tuner_xtal,
+        }
+    }
+
+    /// Set the RTL and tuner crystal frequencies, 0 keeps the current one
+    pub fn set_xtal_freq(&self, rtl_freq: u32, tuner_freq: u32) -> Result<()> {
         if rtl_freq > 0 && !(MIN_RTL_XTAL_FREQ..=MAX_RTL_XTAL_FREQ).contains(&rtl_freq) {
-            return Err(RtlsdrErr(format!(
-                "set_xtal_freq error: rtl_freq {rtl_freq} out of bounds"
-            )));
+            return Err(InvalidXtalFreq {
+                freq: rtl_freq,
+                supported: MIN_RTL_XTAL_FREQ..=MAX_RTL_XTAL_FREQ,
+            });
         }
-        if rtl_freq > 0 && inner.deref().borrow().xtal != rtl_freq {
-            inner.deref().borrow_mut().xtal = rtl_freq;
+        let current = self.get_xtal_freqs();
+        self.apply_xtal_freqs(XtalFreqs {
+            rtl: if rtl_freq > 0 { rtl_freq } else { current.rtl },
+            tuner: if tuner_freq > 0 {
+                tuner_freq
+            } else {
+                current.tuner
+            },
+        })
+    }
 
-            // Update xtal-dependent settings
-            if inner.deref().borrow().rate != 0 {
-                self.set_sample_rate(inner.deref().borrow().rate)?;
-            }
+    /// Go back to the crystals the dongle model ships with
+    pub fn reset_xtal_freqs(&self) -> Result<()> {
+        let blog_v4 = self.i.lock().deref().borrow().blog_v4;
+        self.apply_xtal_freqs(default_xtal_freqs(self.get_tuner_type(), blog_v4))
+    }
+
+    fn apply_xtal_freqs(&self, freqs: XtalFreqs) -> Result<()> {
+        let inner = self.i.lock();
+        inner.deref().borrow_mut().xtal = freqs.rtl;
+        inner.deref().borrow_mut().tuner_xtal = freqs.tuner;
+
+        // Read corrected clock value into tuner
+        let tuner_xtal = self.get_tuner_xtal_freq();
+        if inner.deref().borrow().tuner.get_xtal_freq()? != tuner_xtal {
+            inner.deref().borrow_mut().tuner.set_xtal_freq(tuner_xtal)?;
         }
 
-        if inner.deref().borrow().tuner.get_xtal_freq()? != tuner_freq {
-            if tuner_freq == 0 {
-                inner.deref().borrow_mut().tuner_xtal = inner.deref().borrow().xtal;
-            } else {
-                inner.deref().borrow_mut().tuner_xtal = tuner_freq;
-            }
+        // Update xtal-dependent settings
+        let (rate, if_freq, freq) = {
+            let inner_ref = inner.deref().borrow();
+            (inner_ref.rate, inner_ref.if_freq, inner_ref.freq)
+        };
+        if rate != 0 {
+            self.set_sample_rate(rate)?;
+        }
+        self.set_if_freq(if_freq)?;
+        if freq != 0 {
+            self.set_center_freq(freq)?;
+        }
+        Ok(())
+    }
 
-            // Read corrected clock value into tuner
-            inner
-                .deref()
-                .borrow_mut()
-                .tuner
-                .set_xtal_freq(self.get_tuner_xtal_freq())?;
+    pub fn dump_eeprom(&self) -> Result<[u8; EEPROM_SIZE]> {
+        let mut buf = [0; EEPROM_SIZE];
+        self.handle.read_eeprom(&mut buf, 0, EEPROM_SIZE)?;
+        Ok(buf)
+    }
 
-            // Update xtal-dependent settings
-            if inner.deref().borrow().freq != 0 {
-                self.set_center_freq(inner.deref().borrow().freq)?;
+    /// Write a full EEPROM image and read it back to check it was stored
+    pub fn restore_eeprom(&self, data: &[u8; EEPROM_SIZE]) -> Result<()> {
+        self.handle.write_eeprom(data, 0, EEPROM_SIZE)?;
+        let written = self.dump_eeprom()?;
+        match data.iter().zip(written.iter()).position(|(a, b)| a != b) {
+            Some(offset) => Err(EepromError::VerifyFailed {
+                offset,
+                wrote: data[offset],
+                read: written[offset],
             }
+            .into()),
+            None => Ok(()),
         }
-        Ok(())
+    }
+
+    pub fn get_eeprom_config(&self) -> Result<EepromConfig> {
+        EepromConfig::parse(&self.dump_eeprom()?)
+    }
+
+    /// Update the configuration area of the EEPROM. Takes effect after the device is replugged.
+    pub fn set_eeprom_config(&self, config: &EepromConfig) -> Result<()> {
+        let mut buf = self.dump_eeprom()?;
+        config.write_to(&mut buf)?;
+        self.restore_eeprom(&buf)
     }
 
     pub fn read_sync(&self, buf: &mut [u8]) -> Result<usize> {
         self.handle.bulk_transfer(buf)
     }
 
+    /// Stream samples to `cb` until `cancel_async` is called, keeping `buf_num` transfers
+    /// of `buf_len` bytes in flight. Zero for either selects the default.
+    pub fn read_async(&self, buf_num: u32, buf_len: u32, cb: &mut dyn FnMut(&[u8])) -> Result<()> {
+        let buf_num = if buf_num > 0 {
+            buf_num as usize
+        } else {
+            DEFAULT_BUF_NUMBER
+        };
+        let buf_len = if buf_len > 0 {
+            buf_len as usize
+        } else {
+            DEFAULT_BUF_LENGTH
+        };
+        // Bulk transfers must be a multiple of the max packet size
+        if buf_len % 512 != 0 {
+            return Err(InvalidBufferLength {
+                len: buf_len,
+                multiple: 512,
+            });
+        }
+        let r = self
+            .handle
+            .bulk_transfer_async(buf_num, buf_len, &self.async_cancel, cb);
+        // Cleared when the read ends rather than when it starts, so a cancel that races
+        // the startup still stops it
+        self.async_cancel.store(false, Ordering::Relaxed);
+        r
+    }
+
+    /// `read_async` with the settings each buffer was received with
+    pub fn read_async_with_info(
+        &self,
+        buf_num: u32,
+        buf_len: u32,
+        cb: &mut dyn FnMut(&[u8], BufferInfo),
+    ) -> Result<()> {
+        self.read_async(buf_num, buf_len, &mut |buf| {
+            let info = BufferInfo {
+                center_freq: self.get_rf_freq(),
+                sample_rate: self.get_sample_rate(),
+            };
+            cb(buf, info)
+        })
+    }
+
+    pub fn cancel_async(&self) {
+        self.async_cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn async_cancel_flag(&self) -> Arc<AtomicBool> {
+        self.async_cancel.clone()
+    }
+
     fn init_baseband(&self) -> Result<()> {
         // Init baseband
         // info!("Initialize USB");
@@ -538,8 +1013,8 @@ impl RtlSdr {
         Ok(())
     }
 
-    fn set_sample_freq_correction(&self, ppm: i32) -> Result<()> {
-        let offs = (-ppm * 2_i32.pow(24) / 1_000_000) as i16;
+    fn set_sample_freq_correction(&self, ppm: f64) -> Result<()> {
+        let offs = (-ppm * 2_f64.powi(24) / 1e6).round() as i16;
         self.handle
             .demod_write_reg(1, 0x3f, (offs & 0xff) as u16, 1)?;
         self.handle
@@ -553,28 +1028,19 @@ impl RtlSdr {
         if inner.deref().borrow().force_bt {
             on = true;
         }
-        self.set_gpio_output(gpio_pin)?;
-        self.set_gpio_bit(gpio_pin, on)
+        self.handle.set_gpio_output(gpio_pin)?;
+        self.handle.set_gpio_bit(gpio_pin, on)
     }
 
-    fn set_gpio_bit(&self, mut gpio: u8, val: bool) -> Result<()> {
-        gpio = 1 << gpio;
-        let mut r = self.handle.read_reg(BLOCK_SYS, GPO, 1)?;
-        r = if val {
-            r | gpio as u16
-        } else {
-            r & !gpio as u16
-        };
-        self.handle.write_reg(BLOCK_SYS, GPO, r, 1)?;
-        Ok(())
-    }
+    /// Demod setup for zero-IF tuners, which deliver I and Q on separate ADCs
+    fn set_zero_if(&self) -> Result<()> {
+        self.set_if_freq(0)?;
+
+        // Enable in-phase + Quadrature ADC input
+        self.handle.demod_write_reg(0, 0x08, 0xcd, 1)?;
 
-    fn set_gpio_output(&self, mut gpio: u8) -> Result<()> {
-        gpio = 1 << gpio;
-        let mut r = self.handle.read_reg(BLOCK_SYS, GPD, 1)?;
-        self.handle.write_reg(BLOCK_SYS, GPD, r & !gpio as u16, 1)?;
-        r = self.handle.read_reg(BLOCK_SYS, GPOE, 1)?;
-        self.handle.write_reg(BLOCK_SYS, GPOE, r | gpio as u16, 1)?;
+        // Enable Zero-IF mode
+        self.handle.demod_write_reg(1, 0xb1, 0x1b, 1)?;
         Ok(())
     }
 
@@ -593,7 +1059,10 @@ impl RtlSdr {
         for i in 0..8 {
             let val = fir[i];
             if !(-128..=127).contains(&val) {
-                panic!("i8 FIR coefficient out of bounds! {val}");
+                return Err(InvalidFirCoefficient {
+                    index: i,
+                    value: val,
+                });
             }
             tmp[i] = val as u8;
         }
@@ -604,10 +1073,10 @@ impl RtlSdr {
         for i in (0..8).step_by(2) {
             let val0 = fir[8 + i];
             let val1 = fir[8 + i + 1];
-            if !(-2048..=2047).contains(&val0) {
-                panic!("i12 FIR coefficient out of bounds: {val0}")
-            } else if !(-2048..=2047).contains(&val1) {
-                panic!("i12 FIR coefficient out of bounds: {val1}")
+            for (index, value) in [(8 + i, val0), (8 + i + 1, val1)] {
+                if !(-2048..=2047).contains(&value) {
+                    return Err(InvalidFirCoefficient { index, value });
+                }
             }
             tmp[8 + i * 3 / 2] = (val0 >> 4) as u8;
             tmp[8 + i * 3 / 2 + 1] = ((val0 << 4) | ((val1 >> 8) & 0x0f)) as u8;
@@ -622,8 +1091,36 @@ impl RtlSdr {
         Ok(())
     }
 
-    fn search_tuner(&self) -> Option<&str> {
+    /// Power up just enough of the device to identify its tuner, then power it down again
+    pub fn probe_tuner(&mut self) -> Result<Option<TunerInfo>> {
+        self.handle.claim_interface(INTERFACE_ID)?;
+        self.handle.test_write()?;
+        self.init_baseband()?;
+        self.set_i2c_repeater(true)?;
+        let tuner = self.search_tuner();
+        self.set_i2c_repeater(false)?;
+
+        // Power-off demodulator and ADCs
+        self.handle.write_reg(BLOCK_SYS, DEMOD_CTL, 0x20, 1)?;
+        Ok(tuner)
+    }
+
+    /// Pulse the tuner reset line on GPIO 5
+    fn reset_tuner(&self) -> Result<()> {
+        self.handle.set_gpio_output(5)?;
+        self.handle.set_gpio_bit(5, true)?;
+        self.handle.set_gpio_bit(5, false)
+    }
+
+    fn search_tuner(&self) -> Option<TunerInfo> {
+        let mut reset_done = false;
         for tuner_info in KNOWN_TUNERS.iter() {
+            if tuner_info.probe_reset && !reset_done {
+                if let Err(e) = self.reset_tuner() {
+                    error!("Resetting tuner failed, continuing. Err: {}", e);
+                }
+                reset_done = true;
+            }
             let regval = self
                 .handle
                 .i2c_read_reg(tuner_info.i2c_addr, tuner_info.check_addr);
@@ -634,8 +1131,9 @@ impl RtlSdr {
             match regval {
                 Ok(val) => {
                     // info!("Expecting value {:#02x}, got value {:#02x}", tuner_info.check_val, val);
-                    if val == tuner_info.check_val {
-                        return Some(tuner_info.id);
+                    if val & tuner_info.check_mask == tuner_info.check_val {
+                        info!("Found {} tuner", tuner_info.name);
+                        return Some(*tuner_info);
                     }
                 }
                 Err(e) => {
@@ -646,3 +1144,70 @@ impl RtlSdr {
         None
     }
 }
+
+/// Restate a tuner's `FrequencyOutOfRange` for the frequency `freq` the caller asked for,
+/// mapping the ranges with `map`
+fn out_of_range_for(e: RtlsdrError, freq: u64, map: impl Fn(u64) -> i128) -> RtlsdrError {
+    match e {
+        FrequencyOutOfRange { ranges, .. } => FrequencyOutOfRange {
+            freq,
+            ranges: ranges
+                .iter()
+                .filter_map(|r| {
+                    let (a, b) = (map(*r.start()), map(*r.end()));
+                    let start = a.min(b).max(0);
+                    let end = a.max(b).min(u64::MAX as i128);
+                    (start <= end).then_some(start as u64..=end as u64)
+                })
+                .collect(),
+        },
+        e => e,
+    }
+}
+
+/// Tuner LO offset for offset tuning at `rate`, far enough out to avoid the 1/f noise
+/// around DC
+pub(crate) fn offset_tuning_freq(rate: u32) -> u32 {
+    (rate / 2) * 170 / 100
+}
+
+/// Frequency of a clock that runs `ppm` fast, rounded to the Hz
+fn apply_ppm(freq: u32, ppm: f64) -> u32 {
+    (freq as f64 * (1.0 + ppm / 1e6)).round() as u32
+}
+
+/// DDC frequency word for an IF, in units of the RTL clock / 2^22
+fn if_freq_word(freq: u32, rtl_xtal: u32) -> i32 {
+    -(freq as f64 * (1u32 << 22) as f64 / rtl_xtal as f64) as i32
+}
+
+fn if_freq_word_freq(word: i32, rtl_xtal: u32) -> f64 {
+    -(word as f64) * rtl_xtal as f64 / (1u32 << 22) as f64
+}
+
+/// Tuner crystals of the dongle models that don't run the tuner from the RTL clock, by tuner
+/// type and whether it's an RTL-SDR Blog V4
+const TUNER_XTAL_FREQS: [(TunerType, bool, u32); 2] = [
+    (TunerType::FC2580, false, FC2580_XTAL_FREQ),
+    (TunerType::R828D, false, R828D_XTAL_FREQ),
+];
+
+fn default_tuner_xtal_freq(tuner_type: TunerType, blog_v4: bool) -> Option<u32> {
+    TUNER_XTAL_FREQS
+        .iter()
+        .find(|(t, b, _)| *t == tuner_type && *b == blog_v4)
+        .map(|(_, _, freq)| *freq)
+}
+
+/// Crystals a dongle model ships with
+pub(crate) fn default_xtal_freqs(tuner_type: TunerType, blog_v4: bool) -> XtalFreqs {
+    XtalFreqs {
+        rtl: DEF_RTL_XTAL_FREQ,
+        tuner: default_tuner_xtal_freq(tuner_type, blog_v4).unwrap_or(DEF_RTL_XTAL_FREQ),
+    }
+}
+
+/// The RTL-SDR Blog V4 identifies itself only through its USB strings
+fn is_blog_v4(config: &EepromConfig) -> bool {
+    config.manufacturer == "RTLSDRBlog" && config.product == "Blog V4"
+}
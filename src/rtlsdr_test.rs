@@ -0,0 +1,671 @@
+use crate::device::Device;
+use crate::eeprom::EepromConfig;
+use crate::error::RtlsdrError;
+use crate::rtlsdr::{offset_tuning_freq, RtlSdr, FIR_LEN};
+use crate::tuners::e4k::applied_bandwidth;
+use crate::tuners::r820t::R82XX_IF_FREQ;
+use crate::virtual_device::{VirtualConfig, VirtualDevice, VirtualTuner};
+use crate::{
+    default_xtal_freqs, DirectSampleMode, FreqTranslation, GainStage, StageGain, TunerGain,
+    TunerType, XtalCap, XtalCapValue, XtalFreqs,
+};
+
+fn virtual_sdr() -> (RtlSdr, VirtualDevice) {
+    virtual_sdr_with(VirtualConfig::default())
+}
+
+fn virtual_sdr_with(config: VirtualConfig) -> (RtlSdr, VirtualDevice) {
+    let dev = VirtualDevice::new(config);
+    let mut sdr = RtlSdr::new(Device::from_transport(Box::new(dev.clone())));
+    sdr.init().unwrap();
+    (sdr, dev)
+}
+
+#[test]
+fn test_set_fir_out_of_range() {
+    let (sdr, _) = virtual_sdr();
+    let mut fir = [0; FIR_LEN];
+    fir[3] = 128;
+    assert!(matches!(
+        sdr.set_fir(&fir),
+        Err(RtlsdrError::InvalidFirCoefficient {
+            index: 3,
+            value: 128
+        })
+    ));
+    fir[3] = 0;
+    fir[13] = -2049;
+    assert!(matches!(
+        sdr.set_fir(&fir),
+        Err(RtlsdrError::InvalidFirCoefficient {
+            index: 13,
+            value: -2049
+        })
+    ));
+    fir[13] = 2047;
+    sdr.set_fir(&fir).unwrap();
+}
+
+#[test]
+fn test_invalid_sample_rate() {
+    let (sdr, _) = virtual_sdr();
+    match sdr.set_sample_rate(500_000) {
+        Err(RtlsdrError::InvalidSampleRate { rate, supported }) => {
+            assert_eq!(rate, 500_000);
+            assert_eq!(supported, &crate::SAMPLE_RATE_RANGES);
+        }
+        r => panic!("expected InvalidSampleRate, got {r:?}"),
+    }
+    sdr.set_sample_rate(2_048_000).unwrap();
+}
+
+fn r828d_sdr(eeprom: EepromConfig, tuner_xtal: f64) -> (RtlSdr, VirtualDevice) {
+    let mut config = VirtualConfig {
+        tuner: VirtualTuner::R828D,
+        tuner_xtal,
+        ..Default::default()
+    };
+    eeprom.write_to(&mut config.eeprom).unwrap();
+    let (sdr, dev) = virtual_sdr_with(config);
+    sdr.set_sample_rate(2_048_000).unwrap();
+    (sdr, dev)
+}
+
+fn assert_tuned(dev: &VirtualDevice, freq: f64) {
+    let tuned = dev.tuned_freq();
+    assert!(
+        (tuned - freq).abs() < 1000.0,
+        "tuned to {tuned}, expected {freq}"
+    );
+}
+
+#[test]
+fn test_blog_v4_bands() {
+    let eeprom = EepromConfig {
+        manufacturer: "RTLSDRBlog".to_string(),
+        product: "Blog V4".to_string(),
+        ..Default::default()
+    };
+    let (sdr, dev) = r828d_sdr(eeprom, 28_800_000.0);
+    assert_eq!(sdr.get_tuner_type(), TunerType::R828D);
+
+    // HF goes through the upconverter and the cable 2 input
+    sdr.set_center_freq(7_100_000).unwrap();
+    assert_tuned(&dev, 35_900_000.0);
+    assert_eq!(dev.tuner_reg(0x06) & 0x08, 0x08);
+    assert!(!dev.gpio(5));
+
+    // VHF on cable 1, inside the FM notch band so the notch is off
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert_tuned(&dev, 100_000_000.0);
+    assert_eq!(dev.tuner_reg(0x06) & 0x08, 0x00);
+    assert_eq!(dev.tuner_reg(0x05) & 0x60, 0x60);
+    assert_eq!(dev.tuner_reg(0x17) & 0x08, 0x00);
+    assert!(dev.gpio(5));
+
+    // UHF on air in
+    sdr.set_center_freq(433_920_000).unwrap();
+    assert_tuned(&dev, 433_920_000.0);
+    assert_eq!(dev.tuner_reg(0x05) & 0x60, 0x00);
+    assert_eq!(dev.tuner_reg(0x17) & 0x08, 0x08);
+}
+
+#[test]
+fn test_r828d_input_switch() {
+    // Plain R828D dongles run the tuner from a 16 MHz crystal
+    let (sdr, dev) = r828d_sdr(EepromConfig::default(), 16_000_000.0);
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert_tuned(&dev, 100_000_000.0);
+    assert_eq!(dev.tuner_reg(0x05) & 0x60, 0x60);
+    sdr.set_center_freq(868_000_000).unwrap();
+    assert_tuned(&dev, 868_000_000.0);
+    assert_eq!(dev.tuner_reg(0x05) & 0x60, 0x00);
+}
+
+#[test]
+fn test_r820t_stage_gains() {
+    let (sdr, dev) = virtual_sdr();
+
+    let lna = sdr.get_tuner_stage_gains(GainStage::Lna).unwrap();
+    assert_eq!(lna.len(), 16);
+    assert_eq!(&lna[..4], &[0, 9, 22, 62]);
+    let vga = sdr.get_tuner_stage_gains(GainStage::Vga).unwrap();
+    assert_eq!(vga[0], -47);
+
+    sdr.set_tuner_stage_gain(GainStage::Lna, StageGain::Index(7))
+        .unwrap();
+    assert_eq!(dev.tuner_reg(0x05) & 0x1f, 0x17);
+    sdr.set_tuner_stage_gain(GainStage::Mixer, StageGain::Index(3))
+        .unwrap();
+    assert_eq!(dev.tuner_reg(0x07) & 0x1f, 0x03);
+    sdr.set_tuner_stage_gain(GainStage::Vga, StageGain::Index(12))
+        .unwrap();
+    assert_eq!(dev.tuner_reg(0x0c) & 0x1f, 0x0c);
+
+    sdr.set_tuner_stage_gain(GainStage::Lna, StageGain::Auto)
+        .unwrap();
+    assert_eq!(dev.tuner_reg(0x05) & 0x10, 0x00);
+    sdr.set_tuner_stage_gain(GainStage::Vga, StageGain::Auto)
+        .unwrap();
+    assert_eq!(dev.tuner_reg(0x0c) & 0x10, 0x10);
+    // Setting one stage leaves the others alone
+    assert_eq!(dev.tuner_reg(0x07) & 0x1f, 0x03);
+
+    assert!(matches!(
+        sdr.set_tuner_stage_gain(GainStage::Mixer, StageGain::Index(16)),
+        Err(RtlsdrError::InvalidGainIndex {
+            stage: GainStage::Mixer,
+            index: 16,
+            supported,
+        }) if supported == (0..=15)
+    ));
+}
+
+#[test]
+fn test_pll_lock() {
+    let (sdr, dev) = virtual_sdr_with(VirtualConfig {
+        force_pll_unlock: true,
+        ..Default::default()
+    });
+    assert_eq!(
+        sdr.get_tuner_freq_ranges(),
+        vec![24_000_000..=1_766_000_000]
+    );
+
+    // Without the option an unlocked PLL is only reported
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert_eq!(sdr.get_pll_locked(), Some(false));
+    assert_eq!(sdr.get_center_freq(), 100_000_000);
+    // After bumping the VCO current for the retry, the Blog build keeps its maximum
+    if cfg!(feature = "rtl_sdr_blog") {
+        assert_eq!(dev.tuner_reg(0x12), 0x06);
+    } else {
+        assert_eq!(dev.tuner_reg(0x12) & 0xe0, 0x60);
+    }
+
+    sdr.set_require_pll_lock(true);
+    assert!(matches!(
+        sdr.set_center_freq(433_920_000),
+        Err(RtlsdrError::PllNotLocked { freq: 433_920_000 })
+    ));
+    assert_eq!(sdr.get_center_freq(), 100_000_000);
+    // The tuner went back too
+    let lo = sdr.get_tuner_lo_freq().unwrap();
+    assert!((lo - 100_000_000.0 - 3_570_000.0).abs() < 1000.0, "LO {lo}");
+}
+
+#[test]
+fn test_tuning_error() {
+    let eeprom = EepromConfig {
+        manufacturer: "RTLSDRBlog".to_string(),
+        product: "Blog V4".to_string(),
+        ..Default::default()
+    };
+    let (sdr, dev) = r828d_sdr(eeprom, 28_800_000.0);
+    assert!((sdr.get_if_freq() - 1_625_000.0).abs() < 10.0);
+    // The emulator reports the frequency at the tuner input, after the HF upconverter
+    for (freq, tuner_freq) in [
+        (7_100_000, 35_900_000),
+        (100_000_000, 100_000_000),
+        (433_920_000, 433_920_000),
+    ] {
+        sdr.set_center_freq(freq).unwrap();
+        let error = sdr.get_tuning_error().unwrap();
+        assert!(error != 0.0);
+        assert!(
+            (tuner_freq as f64 + error - dev.tuned_freq()).abs() < 0.01,
+            "error {error} at {freq}"
+        );
+    }
+    let lo = sdr.get_tuner_lo_freq().unwrap();
+    assert!((lo - 435_545_000.0).abs() < 1000.0);
+}
+
+#[test]
+fn test_direct_sampling_off() {
+    let (sdr, dev) = virtual_sdr();
+    sdr.set_sample_rate(2_048_000).unwrap();
+    sdr.set_direct_sampling(DirectSampleMode::On).unwrap();
+    sdr.set_center_freq(14_200_000).unwrap();
+    assert!((sdr.get_if_freq() - 14_200_000.0).abs() < 10.0);
+
+    // Back on the tuner the DDC returns to the R82xx IF
+    sdr.set_direct_sampling(DirectSampleMode::Off).unwrap();
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert!((sdr.get_if_freq() - R82XX_IF_FREQ as f64).abs() < 10.0);
+    assert_tuned(&dev, 100_000_000.0);
+    let error = sdr.get_tuning_error().unwrap();
+    assert!(
+        (100_000_000.0 + error - dev.tuned_freq()).abs() < 0.01,
+        "error {error}"
+    );
+}
+
+#[test]
+fn test_xtal_cap() {
+    let (sdr, dev) = virtual_sdr();
+    sdr.set_sample_rate(2_048_000).unwrap();
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert_eq!(sdr.get_xtal_cap(), Some(XtalCapValue::XtalHighCap0p));
+
+    sdr.set_xtal_cap(XtalCap::Fixed(XtalCapValue::XtalLowCap10p))
+        .unwrap();
+    assert_eq!(sdr.get_xtal_cap(), Some(XtalCapValue::XtalLowCap10p));
+    // Low capacitor drive bit, plus the 10 pF value for 100 MHz
+    assert_eq!(dev.tuner_reg(0x10) & 0x0b, 0x09);
+
+    // The emulated PLL doesn't lock at the power-on dividers, so no value passes
+    assert!(matches!(
+        sdr.set_xtal_cap(XtalCap::Calibrate),
+        Err(RtlsdrError::XtalCalibrationFailed)
+    ));
+    assert_eq!(sdr.get_xtal_cap(), Some(XtalCapValue::XtalHighCap0p));
+    assert_tuned(&dev, 100_000_000.0);
+
+    // Each check keeps the first capacitor that locks, from 30 pF down
+    let (sdr, dev) = virtual_sdr_with(VirtualConfig {
+        xtal_lock_caps: vec![0x01, 0x00],
+        ..Default::default()
+    });
+    sdr.set_sample_rate(2_048_000).unwrap();
+    sdr.set_center_freq(100_000_000).unwrap();
+    sdr.set_tuner_gain(TunerGain::Manual(197)).unwrap();
+    sdr.set_tuner_bandwidth(1_000_000).unwrap();
+    let settings = [0x05, 0x07, 0x0a, 0x0b, 0x0c].map(|reg| dev.tuner_reg(reg));
+    let bw = sdr.get_tuner_bandwidth();
+    sdr.set_xtal_cap(XtalCap::Calibrate).unwrap();
+    assert_eq!(sdr.get_xtal_cap(), Some(XtalCapValue::XtalLowCap10p));
+    assert_eq!(dev.tuner_reg(0x10) & 0x0b, 0x09);
+    assert_tuned(&dev, 100_000_000.0);
+    // The manual gain and the filters survive the search
+    assert_eq!(
+        [0x05, 0x07, 0x0a, 0x0b, 0x0c].map(|reg| dev.tuner_reg(reg)),
+        settings
+    );
+    assert_eq!(dev.tuner_reg(0x05) & 0x10, 0x10);
+    assert_eq!(sdr.get_tuner_bandwidth(), bw);
+
+    let (sdr, _) = virtual_sdr_with(VirtualConfig {
+        tuner: VirtualTuner::E4000,
+        ..Default::default()
+    });
+    assert_eq!(sdr.get_xtal_cap(), None);
+    assert!(matches!(
+        sdr.set_xtal_cap(XtalCap::Calibrate),
+        Err(RtlsdrError::XtalCapUnsupported {
+            tuner: TunerType::E4000
+        })
+    ));
+}
+
+#[test]
+fn test_filter_calibration() {
+    let (sdr, dev) = virtual_sdr();
+    assert_eq!(sdr.get_tuner_filter_cal_code(), Some(0x08));
+    // The calibration tunes the PLL, but that doesn't count as a retune
+    assert_eq!(sdr.get_tuner_lo_freq(), None);
+
+    for bw in [1_000_000, 2_048_000, 8_000_000] {
+        sdr.set_tuner_bandwidth(bw).unwrap();
+        assert_eq!(dev.tuner_reg(0x0a) & 0x0f, 0x08, "bandwidth {bw}");
+    }
+
+    // Standby overwrites the code in the register, the next bandwidth puts it back
+    sdr.set_direct_sampling(DirectSampleMode::On).unwrap();
+    assert_eq!(dev.tuner_reg(0x0a) & 0x0f, 0x06);
+    sdr.set_tuner_bandwidth(2_048_000).unwrap();
+    assert_eq!(dev.tuner_reg(0x0a) & 0x0f, 0x08);
+}
+
+#[test]
+fn test_tuner_bandwidth() {
+    let (sdr, _) = virtual_sdr();
+    assert_eq!(sdr.get_tuner_bandwidth(), 6_000_000);
+    let bandwidths = sdr.get_tuner_bandwidths();
+    assert_eq!(bandwidths.first(), Some(&350_000));
+    assert_eq!(bandwidths.last(), Some(&8_000_000));
+    assert!(bandwidths.windows(2).all(|w| w[0] < w[1]));
+
+    // Snaps to the low-pass filter and high-pass corners
+    sdr.set_tuner_bandwidth(2_048_000).unwrap();
+    assert_eq!(sdr.get_tuner_bandwidth(), 2_050_000);
+    assert!(bandwidths.contains(&2_050_000));
+    assert_eq!(sdr.get_tuner_if_freq().unwrap(), 1_625_000);
+
+    // Anything narrower than the low-pass table gets its narrowest filter
+    sdr.set_tuner_bandwidth(200_000).unwrap();
+    assert_eq!(sdr.get_tuner_bandwidth(), 350_000);
+
+    sdr.set_tuner_bandwidth(7_500_000).unwrap();
+    assert_eq!(sdr.get_tuner_bandwidth(), 8_000_000);
+    assert_eq!(sdr.get_tuner_if_freq().unwrap(), 4_570_000);
+}
+
+#[test]
+fn test_offset_tuning() {
+    assert_eq!(offset_tuning_freq(2_048_000), 1_740_800);
+
+    let (sdr, dev) = r828d_sdr(EepromConfig::default(), 16e6);
+    sdr.set_center_freq(100_000_000).unwrap();
+    // R82xx tuners already use a low IF
+    sdr.set_offset_tuning_bias_tee(false);
+    assert!(matches!(
+        sdr.set_offset_tuning(true),
+        Err(RtlsdrError::OffsetTuningUnsupported {
+            tuner: TunerType::R828D
+        })
+    ));
+    assert!(!sdr.get_offset_tuning());
+    assert!(sdr.set_offset_tuning(false).is_ok());
+    assert!(!dev.gpio(0));
+
+    // Unless it stands in for the bias tee
+    sdr.set_offset_tuning_bias_tee(true);
+    sdr.set_offset_tuning(true).unwrap();
+    assert!(dev.gpio(0));
+    assert!(!sdr.get_offset_tuning());
+    sdr.set_offset_tuning(false).unwrap();
+    assert!(!dev.gpio(0));
+    assert_eq!(sdr.get_center_freq(), 100_000_000);
+}
+
+#[test]
+fn test_offset_tuning_zero_if() {
+    let (sdr, dev) = virtual_sdr_with(VirtualConfig {
+        tuner: VirtualTuner::E4000,
+        ..Default::default()
+    });
+    assert_eq!(sdr.get_tuner_type(), TunerType::E4000);
+    sdr.set_sample_rate(2_048_000).unwrap();
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert!((dev.lo_freq() - 100_000_000.0).abs() < 100.0);
+    assert_eq!(dev.if_freq(), 0.0);
+
+    // The tuner moves below the center frequency and the DDC shifts it back up
+    let offset = offset_tuning_freq(2_048_000);
+    sdr.set_offset_tuning(true).unwrap();
+    assert!(sdr.get_offset_tuning());
+    assert!((dev.lo_freq() - (100_000_000 - offset) as f64).abs() < 100.0);
+    assert!((dev.if_freq() - offset as f64).abs() < 10.0);
+    assert_tuned(&dev, 100_000_000.0);
+    let error = sdr.get_tuning_error().unwrap();
+    assert!(
+        (dev.tuned_freq() - 100_000_000.0 - error).abs() < 0.01,
+        "error {error}"
+    );
+    // The filter widens to pass the offset signal, the channel filter's closest is 3.4 MHz
+    assert_eq!(sdr.get_tuner_bandwidth(), applied_bandwidth(2 * offset));
+    assert_eq!(dev.tuner_reg(0x12) & 0x1f, 12);
+
+    // Retunes keep the offset
+    sdr.set_center_freq(433_920_000).unwrap();
+    assert!((dev.lo_freq() - (433_920_000 - offset) as f64).abs() < 100.0);
+    assert_tuned(&dev, 433_920_000.0);
+    // The LO and DDC errors add up on a zero-IF tuner
+    for freq in [145_123_456, 433_920_000] {
+        sdr.set_center_freq(freq).unwrap();
+        let error = sdr.get_tuning_error().unwrap();
+        assert!(
+            (dev.tuned_freq() - freq as f64 - error).abs() < 0.01,
+            "error {error} at {freq}"
+        );
+    }
+
+    sdr.set_offset_tuning(false).unwrap();
+    assert!((dev.lo_freq() - 433_920_000.0).abs() < 100.0);
+    assert_eq!(dev.if_freq(), 0.0);
+    assert_eq!(sdr.get_tuner_bandwidth(), applied_bandwidth(2_048_000));
+
+    sdr.set_direct_sampling(DirectSampleMode::On).unwrap();
+    assert!(matches!(
+        sdr.set_offset_tuning(true),
+        Err(RtlsdrError::OffsetTuningInDirectSampling)
+    ));
+}
+
+#[test]
+fn test_e4k_if_gain() {
+    let (sdr, _dev) = virtual_sdr_with(VirtualConfig {
+        tuner: VirtualTuner::E4000,
+        ..Default::default()
+    });
+    sdr.set_tuner_if_gain(2, 90).unwrap();
+    assert!(matches!(
+        sdr.set_tuner_if_gain(2, 120),
+        Err(RtlsdrError::InvalidIfGain { stage: 2, gain: 120, supported })
+            if supported == [0, 30, 60, 90]
+    ));
+    assert!(matches!(
+        sdr.set_tuner_if_gain(7, 30),
+        Err(RtlsdrError::InvalidIfStage { stage: 7, .. })
+    ));
+    // Only the combined LNA and mixer gain is settable
+    assert!(matches!(
+        sdr.set_tuner_stage_gain(GainStage::Lna, StageGain::Index(0)),
+        Err(RtlsdrError::GainStageUnsupported {
+            stage: GainStage::Lna,
+            tuner: TunerType::E4000
+        })
+    ));
+}
+
+#[test]
+fn test_freq_correction() {
+    // Tuner crystal running 20 ppm fast
+    let (sdr, dev) = r828d_sdr(EepromConfig::default(), 16e6 * (1.0 + 20e-6));
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert!((dev.tuned_freq() - 100_000_000.0).abs() > 1500.0);
+
+    sdr.set_freq_correction_ppm(20.0).unwrap();
+    assert!((dev.tuned_freq() - 100_000_000.0).abs() < 200.0);
+    assert_eq!(sdr.get_tuner_xtal_freq(), 16_000_320);
+    assert_eq!(sdr.get_xtal_freq(), 28_800_576);
+    // -20 ppm of 2^24 is -335.5, 14 bit two's complement
+    assert_eq!(dev.demod_reg(1, 0x3e), 0x3e);
+    assert_eq!(dev.demod_reg(1, 0x3f), 0xb0);
+
+    sdr.set_freq_correction_ppm(0.3).unwrap();
+    assert_eq!(sdr.get_freq_correction_ppm(), 0.3);
+    assert_eq!(sdr.get_freq_correction(), 0);
+    assert_eq!(sdr.get_xtal_freq(), 28_800_009);
+    assert!(matches!(
+        sdr.set_freq_correction_ppm(500.0),
+        Err(RtlsdrError::InvalidFreqCorrection { ppm, supported })
+            if ppm == 500.0 && supported == (-488.0..=488.0)
+    ));
+    assert!(sdr.set_freq_correction_ppm(f64::NAN).is_err());
+    assert_eq!(sdr.get_freq_correction_ppm(), 0.3);
+}
+
+#[test]
+fn test_xtal_freq() {
+    assert_eq!(
+        default_xtal_freqs(TunerType::R828D, false),
+        XtalFreqs {
+            rtl: 28_800_000,
+            tuner: 16_000_000
+        }
+    );
+    assert_eq!(default_xtal_freqs(TunerType::R828D, true).tuner, 28_800_000);
+    assert_eq!(
+        default_xtal_freqs(TunerType::FC2580, false).tuner,
+        16_384_000
+    );
+
+    // Tuner crystal 500 Hz high, on a dongle with a separate 16 MHz one
+    let (sdr, dev) = r828d_sdr(EepromConfig::default(), 16_000_500.0);
+    assert_eq!(sdr.get_xtal_freqs().tuner, 16_000_000);
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert!((dev.tuned_freq() - 100_000_000.0).abs() > 2000.0);
+    sdr.set_xtal_freq(0, 16_000_500).unwrap();
+    assert!((dev.tuned_freq() - 100_000_000.0).abs() < 200.0);
+
+    // A new RTL crystal reprograms the resampler, the tuner keeps its custom crystal
+    sdr.set_xtal_freq(28_800_900, 0).unwrap();
+    assert_eq!(
+        sdr.get_xtal_freqs(),
+        XtalFreqs {
+            rtl: 28_800_900,
+            tuner: 16_000_500
+        }
+    );
+    assert!((dev.sample_rate() - 2_048_000.0 * 28_800_000.0 / 28_800_900.0).abs() < 1.0);
+    assert!((dev.tuned_freq() - 100_000_000.0).abs() < 200.0);
+    assert!(matches!(
+        sdr.set_xtal_freq(27_000_000, 0),
+        Err(RtlsdrError::InvalidXtalFreq {
+            freq: 27_000_000,
+            supported,
+        }) if supported == (28_799_000..=28_801_000)
+    ));
+
+    // Reset goes back to the model defaults
+    sdr.reset_xtal_freqs().unwrap();
+    assert_eq!(
+        sdr.get_xtal_freqs(),
+        default_xtal_freqs(TunerType::R828D, false)
+    );
+    assert!((dev.sample_rate() - 2_048_000.0).abs() < 1.0);
+
+    // With one shared crystal both have to be set
+    let (sdr, _) = virtual_sdr();
+    sdr.set_xtal_freq(28_800_900, 0).unwrap();
+    assert_eq!(sdr.get_xtal_freqs().tuner, 28_800_000);
+    sdr.set_xtal_freq(28_800_900, 28_800_900).unwrap();
+    assert_eq!(sdr.get_xtal_freqs().tuner, 28_800_900);
+    sdr.reset_xtal_freqs().unwrap();
+    assert_eq!(sdr.get_xtal_freqs().tuner, 28_800_000);
+}
+
+#[test]
+fn test_freq_translation() {
+    let (sdr, dev) = r828d_sdr(EepromConfig::default(), 16e6);
+    let tuner_range = sdr.get_tuner_freq_ranges()[0].clone();
+    let (min, max) = (*tuner_range.start(), *tuner_range.end());
+    match sdr.set_rf_freq(5_000_000_000) {
+        Err(RtlsdrError::FrequencyOutOfRange { freq, ranges }) => {
+            assert_eq!(freq, 5_000_000_000);
+            assert_eq!(ranges, vec![min..=max]);
+        }
+        r => panic!("expected FrequencyOutOfRange, got {r:?}"),
+    }
+
+    // Ham It Up style upconverter
+    let upconverter = FreqTranslation {
+        lo_offset: 125_000_000,
+        inverted: false,
+    };
+    sdr.set_freq_translation(upconverter).unwrap();
+    sdr.set_rf_freq(7_100_000).unwrap();
+    assert_eq!(sdr.get_rf_freq(), 7_100_000);
+    assert_eq!(sdr.get_center_freq(), 132_100_000);
+    assert_tuned(&dev, 132_100_000.0);
+
+    // Changing the converter keeps the RF frequency, or fails without changing anything
+    let lnb = FreqTranslation {
+        lo_offset: -9_750_000_000,
+        inverted: false,
+    };
+    // Ranges are given as RF frequencies
+    match sdr.set_freq_translation(lnb) {
+        Err(RtlsdrError::FrequencyOutOfRange { freq, ranges }) => {
+            assert_eq!(freq, 7_100_000);
+            assert_eq!(ranges, vec![min + 9_750_000_000..=max + 9_750_000_000]);
+        }
+        r => panic!("expected FrequencyOutOfRange, got {r:?}"),
+    }
+    assert_eq!(sdr.get_freq_translation(), upconverter);
+    sdr.set_freq_translation(FreqTranslation {
+        lo_offset: 100_000_000,
+        inverted: false,
+    })
+    .unwrap();
+    assert_eq!(sdr.get_rf_freq(), 7_100_000);
+    assert_tuned(&dev, 107_100_000.0);
+
+    // 10 GHz through an LNB
+    let (sdr, dev) = r828d_sdr(EepromConfig::default(), 16e6);
+    sdr.set_freq_translation(lnb).unwrap();
+    sdr.set_rf_freq(10_489_500_000).unwrap();
+    assert_eq!(sdr.get_rf_freq(), 10_489_500_000);
+    assert_tuned(&dev, 739_500_000.0);
+
+    // The R828D's low IF is mirrored, which the demod undoes
+    assert_eq!(dev.demod_reg(1, 0x15), 0x01);
+
+    // LO above the RF mirrors the spectrum once more, and the tuning error
+    let inverted = FreqTranslation {
+        lo_offset: 11_250_000_000,
+        inverted: true,
+    };
+    sdr.set_freq_translation(inverted).unwrap();
+    assert_eq!(dev.demod_reg(1, 0x15), 0x00);
+    assert_eq!(sdr.get_rf_freq(), 10_489_500_000);
+    assert_eq!(sdr.get_center_freq(), 760_500_000);
+    assert_tuned(&dev, 760_500_000.0);
+    let error = sdr.get_tuning_error().unwrap();
+    assert!((error + (dev.tuned_freq() - 760_500_000.0)).abs() < 1.0);
+
+    // Direct sampling has no tuner inversion, so only the converter's is undone
+    sdr.set_direct_sampling(DirectSampleMode::On).unwrap();
+    assert_eq!(dev.demod_reg(1, 0x15), 0x01);
+    sdr.set_direct_sampling(DirectSampleMode::Off).unwrap();
+    assert_eq!(dev.demod_reg(1, 0x15), 0x00);
+    sdr.set_freq_translation(lnb).unwrap();
+    assert_eq!(dev.demod_reg(1, 0x15), 0x01);
+}
+
+#[test]
+fn test_read_async_with_info() {
+    let (sdr, dev) = virtual_sdr();
+    sdr.set_sample_rate(2_048_000).unwrap();
+    sdr.set_freq_translation(FreqTranslation {
+        lo_offset: 125_000_000,
+        inverted: false,
+    })
+    .unwrap();
+    sdr.set_rf_freq(7_100_000).unwrap();
+
+    let mut freqs = vec![];
+    sdr.read_async_with_info(1, 512, &mut |_, info| {
+        assert_eq!(info.sample_rate, 2_048_000);
+        freqs.push(info.center_freq);
+        match freqs.len() {
+            2 => sdr.set_rf_freq(14_200_000).unwrap(),
+            4 => sdr.cancel_async(),
+            _ => {}
+        }
+    })
+    .unwrap();
+    // Buffers carry the RF frequency, not the one the dongle is tuned to
+    assert_eq!(freqs, [7_100_000, 7_100_000, 14_200_000, 14_200_000]);
+    assert_tuned(&dev, 139_200_000.0);
+}
+
+#[test]
+fn test_cancel_async() {
+    let (sdr, _) = virtual_sdr();
+    // A cancel before the read starts isn't lost
+    sdr.cancel_async();
+    let mut bufs = 0;
+    sdr.read_async(1, 512, &mut |_| bufs += 1).unwrap();
+    assert_eq!(bufs, 0);
+
+    // And doesn't stop the next read
+    sdr.read_async(1, 512, &mut |_| {
+        bufs += 1;
+        if bufs == 3 {
+            sdr.cancel_async();
+        }
+    })
+    .unwrap();
+    assert_eq!(bufs, 3);
+
+    assert!(matches!(
+        sdr.read_async(1, 1000, &mut |_| {}),
+        Err(RtlsdrError::InvalidBufferLength {
+            len: 1000,
+            multiple: 512
+        })
+    ));
+}
@@ -0,0 +1,752 @@
+use super::{LoFreq, Tuner, TunerGain, TunerInfo};
+use crate::device::Device;
+use crate::error::Result;
+use crate::error::RtlsdrError::{InvalidGain, InvalidIfGain, InvalidIfStage};
+use crate::GainStage;
+use log::{info, warn};
+use std::ops::RangeInclusive;
+
+const E4K_I2C_ADDR: u8 = 0xc8;
+const E4K_CHECK_ADDR: u8 = 0x02;
+const E4K_CHECK_VAL: u8 = 0x40;
+
+// Tuning range the datasheet specifies
+const E4K_FREQ_MIN: u32 = 52_000_000;
+const E4K_FREQ_MAX: u32 = 2_200_000_000;
+const E4K_PLL_Y: u64 = 65536;
+
+// Registers (reg_e4k.h)
+const REG_MASTER1: u8 = 0x00;
+const REG_CLK_INP: u8 = 0x05;
+const REG_REF_CLK: u8 = 0x06;
+const REG_SYNTH1: u8 = 0x07;
+const REG_SYNTH3: u8 = 0x09;
+const REG_SYNTH4: u8 = 0x0a;
+const REG_SYNTH5: u8 = 0x0b;
+const REG_SYNTH7: u8 = 0x0d;
+const REG_FILT1: u8 = 0x10;
+const REG_FILT2: u8 = 0x11;
+const REG_FILT3: u8 = 0x12;
+const REG_GAIN1: u8 = 0x14;
+const REG_GAIN2: u8 = 0x15;
+const REG_GAIN3: u8 = 0x16;
+const REG_GAIN4: u8 = 0x17;
+const REG_AGC1: u8 = 0x1a;
+const REG_AGC4: u8 = 0x1d;
+const REG_AGC5: u8 = 0x1e;
+const REG_AGC6: u8 = 0x1f;
+const REG_AGC7: u8 = 0x20;
+const REG_AGC11: u8 = 0x24;
+const REG_DC1: u8 = 0x29;
+const REG_DC2: u8 = 0x2a;
+const REG_DC3: u8 = 0x2b;
+const REG_DC4: u8 = 0x2c;
+const REG_DC5: u8 = 0x2d;
+const REG_DC7: u8 = 0x2f;
+const REG_QLUT0: u8 = 0x50;
+const REG_ILUT0: u8 = 0x60;
+const REG_DCTIME1: u8 = 0x70;
+const REG_DCTIME2: u8 = 0x71;
+const REG_BIAS: u8 = 0x78;
+const REG_CLKOUT_PWDN: u8 = 0x7a;
+
+const MASTER1_RESET: u8 = 1 << 0;
+const MASTER1_NORM_STBY: u8 = 1 << 1;
+const MASTER1_POR_DET: u8 = 1 << 2;
+const SYNTH1_PLL_LOCK: u8 = 1 << 0;
+const SYNTH7_3PHASE_EN: u8 = 1 << 3;
+const FILT3_DISABLE: u8 = 1 << 5;
+const AGC7_MIX_GAIN_AUTO: u8 = 1 << 0;
+const DC1_CAL_REQ: u8 = 1 << 0;
+const DC5_I_LUT_EN: u8 = 1 << 0;
+const DC5_Q_LUT_EN: u8 = 1 << 1;
+const DC5_RANGE_DET_EN: u8 = 1 << 2;
+const CLKOUT_DISABLE: u8 = 0x96;
+
+const AGC1_MOD_MASK: u8 = 0x0f;
+const AGC_MOD_SERIAL: u8 = 0x0;
+const AGC_MOD_IF_SERIAL_LNA_AUTON: u8 = 0x9;
+
+pub const GAINS: [i32; 14] = [
+    -10, 15, 40, 65, 90, 115, 140, 165, 190, 215, 240, 290, 340, 420,
+];
+
+// LNA gain in tenths of a dB and the GAIN1 register value selecting it
+const LNA_GAINS: [(i32, u8); 13] = [
+    (-50, 0),
+    (-25, 1),
+    (0, 4),
+    (25, 5),
+    (50, 6),
+    (75, 7),
+    (100, 8),
+    (125, 9),
+    (150, 10),
+    (175, 11),
+    (200, 12),
+    (250, 13),
+    (300, 14),
+];
+
+/// Register bit field, for the gain stages and filters that share registers
+struct RegField {
+    reg: u8,
+    shift: u8,
+    width: u8,
+}
+
+impl RegField {
+    const fn mask(&self) -> u8 {
+        (((1u16 << self.width) - 1) as u8) << self.shift
+    }
+}
+
+// IF gain stages 1-6 in dB, indexed by their field value
+const IF_STAGE_GAINS: [&[i32]; 6] = [
+    &[-3, 6],
+    &[0, 3, 6, 9],
+    &[0, 3, 6, 9],
+    &[0, 1, 2, 2],
+    &[3, 6, 9, 12, 15, 15, 15, 15],
+    &[3, 6, 9, 12, 15, 15, 15, 15],
+];
+const IF_STAGE_FIELDS: [RegField; 6] = [
+    RegField {
+        reg: REG_GAIN3,
+        shift: 0,
+        width: 1,
+    },
+    RegField {
+        reg: REG_GAIN3,
+        shift: 1,
+        width: 2,
+    },
+    RegField {
+        reg: REG_GAIN3,
+        shift: 3,
+        width: 2,
+    },
+    RegField {
+        reg: REG_GAIN3,
+        shift: 5,
+        width: 2,
+    },
+    RegField {
+        reg: REG_GAIN4,
+        shift: 0,
+        width: 3,
+    },
+    RegField {
+        reg: REG_GAIN4,
+        shift: 3,
+        width: 3,
+    },
+];
+const IF_STAGE_MAX: [i32; 6] = [6, 9, 9, 2, 15, 15];
+
+/// Mixer gain and IF stage 1 gain combinations with their own DC offset table entry
+struct DcGainComb {
+    mixer_gain: i32,
+    if1_gain: i32,
+    reg: u8,
+}
+
+const DC_GAIN_COMBS: [DcGainComb; 4] = [
+    DcGainComb {
+        mixer_gain: 4,
+        if1_gain: -3,
+        reg: REG_QLUT0,
+    },
+    DcGainComb {
+        mixer_gain: 4,
+        if1_gain: 6,
+        reg: REG_QLUT0 + 1,
+    },
+    DcGainComb {
+        mixer_gain: 12,
+        if1_gain: -3,
+        reg: REG_QLUT0 + 2,
+    },
+    DcGainComb {
+        mixer_gain: 12,
+        if1_gain: 6,
+        reg: REG_QLUT0 + 3,
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Band {
+    Vhf2 = 0,
+    Vhf3 = 1,
+    Uhf = 2,
+    L = 3,
+}
+
+impl Band {
+    fn for_freq(flo: u32) -> Band {
+        if flo < 140_000_000 {
+            Band::Vhf2
+        } else if flo < 350_000_000 {
+            Band::Vhf3
+        } else if flo < 1_135_000_000 {
+            Band::Uhf
+        } else {
+            Band::L
+        }
+    }
+}
+
+// Upper frequency, SYNTH7 value (3-phase mixing flag and R index) and R divider
+const PLL_VARS: [(u32, u8, u32); 10] = [
+    (72_400_000, SYNTH7_3PHASE_EN | 7, 48),
+    (81_200_000, SYNTH7_3PHASE_EN | 6, 40),
+    (108_300_000, SYNTH7_3PHASE_EN | 5, 32),
+    (162_500_000, SYNTH7_3PHASE_EN | 4, 24),
+    (216_600_000, SYNTH7_3PHASE_EN | 3, 16),
+    (325_000_000, SYNTH7_3PHASE_EN | 2, 12),
+    (350_000_000, SYNTH7_3PHASE_EN | 1, 8),
+    (432_000_000, 3, 8),
+    (667_000_000, 2, 6),
+    (1_200_000_000, 1, 4),
+];
+
+// RF filter centre frequencies
+const RF_FILT_CENTER_UHF: [u32; 16] = [
+    360_000_000,
+    380_000_000,
+    405_000_000,
+    425_000_000,
+    450_000_000,
+    475_000_000,
+    505_000_000,
+    540_000_000,
+    575_000_000,
+    615_000_000,
+    670_000_000,
+    720_000_000,
+    760_000_000,
+    840_000_000,
+    890_000_000,
+    970_000_000,
+];
+const RF_FILT_CENTER_L: [u32; 16] = [
+    1_300_000_000,
+    1_320_000_000,
+    1_360_000_000,
+    1_410_000_000,
+    1_445_000_000,
+    1_460_000_000,
+    1_490_000_000,
+    1_530_000_000,
+    1_560_000_000,
+    1_590_000_000,
+    1_640_000_000,
+    1_660_000_000,
+    1_680_000_000,
+    1_700_000_000,
+    1_720_000_000,
+    1_750_000_000,
+];
+
+// IF filter bandwidths, indexed by their field value
+const MIX_FILTER_BW: [u32; 16] = [
+    27_000_000, 27_000_000, 27_000_000, 27_000_000, 27_000_000, 27_000_000, 27_000_000, 27_000_000,
+    4_600_000, 4_200_000, 3_800_000, 3_400_000, 3_300_000, 2_700_000, 2_300_000, 1_900_000,
+];
+const IFRC_FILTER_BW: [u32; 16] = [
+    21_400_000, 21_000_000, 17_600_000, 14_700_000, 12_400_000, 10_600_000, 9_000_000, 7_700_000,
+    6_400_000, 5_300_000, 4_400_000, 3_400_000, 2_600_000, 1_800_000, 1_200_000, 1_000_000,
+];
+const IFCH_FILTER_BW: [u32; 32] = [
+    5_500_000, 5_300_000, 5_000_000, 4_800_000, 4_600_000, 4_400_000, 4_300_000, 4_100_000,
+    3_900_000, 3_800_000, 3_700_000, 3_600_000, 3_400_000, 3_300_000, 3_200_000, 3_100_000,
+    3_000_000, 2_950_000, 2_900_000, 2_800_000, 2_750_000, 2_700_000, 2_600_000, 2_550_000,
+    2_500_000, 2_450_000, 2_400_000, 2_300_000, 2_280_000, 2_240_000, 2_200_000, 2_150_000,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum IfFilter {
+    Mix,
+    Chan,
+    Rc,
+}
+
+impl IfFilter {
+    fn bandwidths(&self) -> &'static [u32] {
+        match self {
+            IfFilter::Mix => &MIX_FILTER_BW,
+            IfFilter::Chan => &IFCH_FILTER_BW,
+            IfFilter::Rc => &IFRC_FILTER_BW,
+        }
+    }
+
+    fn field(&self) -> RegField {
+        match self {
+            IfFilter::Mix => RegField {
+                reg: REG_FILT2,
+                shift: 4,
+                width: 4,
+            },
+            IfFilter::Chan => RegField {
+                reg: REG_FILT3,
+                shift: 0,
+                width: 5,
+            },
+            IfFilter::Rc => RegField {
+                reg: REG_FILT2,
+                shift: 0,
+                width: 4,
+            },
+        }
+    }
+}
+
+/// PLL settings for one LO frequency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PllParams {
+    /// SYNTH7: 3-phase mixing enable and R divider index
+    pub r_idx: u8,
+    pub r: u32,
+    pub z: u8,
+    pub x: u16,
+    /// LO frequency the settings actually produce
+    pub flo: u32,
+}
+
+/// Compute the integer (Z) and fractional (X / 65536) VCO multipliers and the output
+/// divider R for an LO frequency (e4k_compute_pll_params)
+pub(crate) fn compute_pll_params(fosc: u32, intended_flo: u32) -> Option<PllParams> {
+    if !(16_000_000..=30_000_000).contains(&fosc) {
+        return None;
+    }
+    let (r_idx, r) = PLL_VARS
+        .iter()
+        .find(|(freq, _, _)| intended_flo < *freq)
+        .map_or((0, 2), |(_, r_idx, r)| (*r_idx, *r));
+
+    let intended_fvco = intended_flo as u64 * r as u64;
+    let z = intended_fvco / fosc as u64;
+    if z > 255 {
+        return None;
+    }
+    let remainder = intended_fvco - fosc as u64 * z;
+    let x = (remainder * E4K_PLL_Y / fosc as u64) as u16;
+
+    let fvco = fosc as u64 * z + fosc as u64 * x as u64 / E4K_PLL_Y;
+    Some(PllParams {
+        r_idx,
+        r,
+        z: z as u8,
+        x,
+        flo: (fvco / r as u64) as u32,
+    })
+}
+
+impl PllParams {
+    /// Exact LO frequency, where `flo` is truncated to whole Hz
+    pub fn lo_freq(&self, fosc: u32) -> f64 {
+        fosc as f64 * (self.z as f64 + self.x as f64 / E4K_PLL_Y as f64) / self.r as f64
+    }
+}
+
+/// Index of the entry in `arr` closest to `freq`
+fn closest_index(arr: &[u32], freq: u32) -> usize {
+    arr.iter()
+        .enumerate()
+        .min_by_key(|(_, f)| f.abs_diff(freq))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Bandwidth the Mix, RC and channel filters together give when each is set closest to
+/// `bw`
+pub(crate) fn applied_bandwidth(bw: u32) -> u32 {
+    [IfFilter::Mix, IfFilter::Rc, IfFilter::Chan]
+        .iter()
+        .map(|filter| filter.bandwidths()[closest_index(filter.bandwidths(), bw)])
+        .min()
+        .unwrap_or(bw)
+}
+
+/// RF filter for the band and LO frequency. VHF has no selectable filter.
+pub(crate) fn rf_filter_index(flo: u32) -> u8 {
+    match Band::for_freq(flo) {
+        Band::Vhf2 | Band::Vhf3 => 0,
+        Band::Uhf => closest_index(&RF_FILT_CENTER_UHF, flo) as u8,
+        Band::L => closest_index(&RF_FILT_CENTER_L, flo) as u8,
+    }
+}
+
+pub const TUNER_ID: &str = "e4000";
+
+pub const TUNER_INFO: TunerInfo = TunerInfo {
+    id: TUNER_ID,
+    name: "Elonics E4000",
+    i2c_addr: E4K_I2C_ADDR,
+    check_addr: E4K_CHECK_ADDR,
+    check_val: E4K_CHECK_VAL,
+    check_mask: 0xff,
+    probe_reset: false,
+};
+
+/// Driver for the Elonics E4000, a zero-IF tuner
+#[derive(Debug)]
+pub struct E4k {
+    pub info: TunerInfo,
+    // Reference oscillator, the RTL2832 clock on all known dongles
+    fosc: u32,
+    has_lock: bool,
+    lo: Option<LoFreq>,
+    bandwidth: u32,
+}
+
+impl E4k {
+    pub fn new(_handle: &mut Device) -> E4k {
+        E4k {
+            info: TUNER_INFO,
+            fosc: 0,
+            has_lock: false,
+            lo: None,
+            bandwidth: 0,
+        }
+    }
+}
+
+impl Tuner for E4k {
+    // e4k_init
+    fn init(&mut self, handle: &Device) -> Result<()> {
+        // Dummy I2C read, which the E4000 won't acknowledge
+        let _ = self.read_reg(handle, 0);
+
+        // Reset everything and clear the POR indicator
+        self.write_reg(
+            handle,
+            REG_MASTER1,
+            MASTER1_RESET | MASTER1_NORM_STBY | MASTER1_POR_DET,
+        )?;
+
+        // Configure clock input
+        self.write_reg(handle, REG_CLK_INP, 0x00)?;
+
+        // Disable clock output
+        self.write_reg(handle, REG_REF_CLK, 0x00)?;
+        self.write_reg(handle, REG_CLKOUT_PWDN, CLKOUT_DISABLE)?;
+
+        self.magic_init(handle)?;
+
+        // Set common mode voltage a bit higher for more margin (850 mV)
+        self.write_reg_mask(handle, REG_DC7, 4, 0x07)?;
+
+        // Calibrate the DC offset lookup tables
+        self.dc_offset_gen_table(handle)?;
+
+        // LNA AGC thresholds and loop rate
+        self.write_reg(handle, REG_AGC4, 0x10)?;
+        self.write_reg(handle, REG_AGC5, 0x04)?;
+        self.write_reg(handle, REG_AGC6, 0x1a)?;
+
+        // Use auto-gain as default
+        self.enable_manual_gain(handle, false)?;
+
+        // Select moderate gain levels
+        self.if_gain_set(handle, 1, 6)?;
+        self.if_gain_set(handle, 2, 0)?;
+        self.if_gain_set(handle, 3, 0)?;
+        self.if_gain_set(handle, 4, 0)?;
+        self.if_gain_set(handle, 5, 9)?;
+        self.if_gain_set(handle, 6, 9)?;
+
+        // Set the most narrow filter we can possibly use
+        self.if_filter_bw_set(handle, IfFilter::Mix, 1_900_000)?;
+        self.if_filter_bw_set(handle, IfFilter::Rc, 1_000_000)?;
+        self.if_filter_bw_set(handle, IfFilter::Chan, 2_150_000)?;
+        self.bandwidth = applied_bandwidth(0);
+        self.write_reg_mask(handle, REG_FILT3, 0, FILT3_DISABLE)?;
+
+        // Correct DC offset from the lookup tables, but disable time variant correction
+        self.write_reg_mask(handle, REG_DC5, DC5_I_LUT_EN | DC5_Q_LUT_EN, 0x03)?;
+        self.write_reg_mask(handle, REG_DCTIME1, 0, 0x03)?;
+        self.write_reg_mask(handle, REG_DCTIME2, 0, 0x03)?;
+        Ok(())
+    }
+
+    fn get_info(&self) -> Result<TunerInfo> {
+        Ok(self.info)
+    }
+
+    fn get_gains(&self) -> Result<Vec<i32>> {
+        Ok(GAINS.to_vec())
+    }
+
+    fn read_gain(&self, handle: &Device) -> Result<i32> {
+        let lna_val = self.read_reg(handle, REG_GAIN1)? & 0x0f;
+        let lna = LNA_GAINS
+            .iter()
+            .rev()
+            .find(|(_, val)| *val <= lna_val)
+            .map_or(LNA_GAINS[0].0, |(gain, _)| *gain);
+        let mixer = if self.read_reg(handle, REG_GAIN2)? & 0x01 != 0 {
+            120
+        } else {
+            40
+        };
+        Ok(lna + mixer)
+    }
+
+    fn set_gain(&mut self, handle: &Device, gain: TunerGain) -> Result<()> {
+        match gain {
+            TunerGain::Auto => self.enable_manual_gain(handle, false),
+            TunerGain::Manual(gain) => {
+                self.enable_manual_gain(handle, true)?;
+                // Only the combinations in GAINS are valid, so use the closest one
+                let gain = *GAINS
+                    .iter()
+                    .min_by_key(|g| g.abs_diff(gain))
+                    .expect("gain table is not empty");
+                let mixer_gain = if gain > 340 { 12 } else { 4 };
+                self.set_lna_gain(handle, (gain - mixer_gain * 10).min(300))?;
+                self.mixer_gain_set(handle, mixer_gain)
+            }
+        }
+    }
+
+    /// `gain` is in tenths of a dB, as for the other gain settings
+    fn set_if_gain(&mut self, handle: &Device, stage: i32, gain: i32) -> Result<()> {
+        self.if_gain_set(handle, stage, gain / 10)
+    }
+
+    // e4k_tune_freq
+    fn set_freq(&mut self, handle: &Device, freq: u32) -> Result<()> {
+        if !(E4K_FREQ_MIN..=E4K_FREQ_MAX).contains(&freq) {
+            return Err(self.out_of_range(freq));
+        }
+        let params = compute_pll_params(self.fosc, freq).ok_or_else(|| self.out_of_range(freq))?;
+        info!("[E4K] PLL params for {} Hz: {:?}", freq, params);
+
+        // Program R and 3-phase/2-phase mixing, Z and X. The VCO is in auto
+        // calibration mode, so there's no need to trigger it.
+        self.write_reg(handle, REG_SYNTH7, params.r_idx)?;
+        self.write_reg(handle, REG_SYNTH3, params.z)?;
+        self.write_reg(handle, REG_SYNTH4, params.x as u8)?;
+        self.write_reg(handle, REG_SYNTH5, (params.x >> 8) as u8)?;
+        self.lo = Some(LoFreq {
+            requested: freq,
+            actual: params.lo_freq(self.fosc),
+        });
+
+        self.band_set(handle, Band::for_freq(params.flo))?;
+        self.write_reg_mask(handle, REG_FILT1, rf_filter_index(params.flo), 0x0f)?;
+
+        self.has_lock = self.read_reg(handle, REG_SYNTH1)? & SYNTH1_PLL_LOCK != 0;
+        if !self.has_lock {
+            warn!("[E4K] PLL not locked for {} Hz!", freq);
+        }
+        Ok(())
+    }
+
+    // e4000_set_bw
+    fn set_bandwidth(&mut self, handle: &Device, bw: u32, _rate: u32) -> Result<()> {
+        self.if_filter_bw_set(handle, IfFilter::Mix, bw)?;
+        self.if_filter_bw_set(handle, IfFilter::Rc, bw)?;
+        self.if_filter_bw_set(handle, IfFilter::Chan, bw)?;
+        self.bandwidth = applied_bandwidth(bw);
+        Ok(())
+    }
+
+    fn get_bandwidths(&self) -> Vec<u32> {
+        let mut bandwidths: Vec<u32> = [IfFilter::Mix, IfFilter::Rc, IfFilter::Chan]
+            .iter()
+            .flat_map(|filter| filter.bandwidths())
+            .map(|bw| applied_bandwidth(*bw))
+            .collect();
+        bandwidths.sort_unstable();
+        bandwidths.dedup();
+        bandwidths
+    }
+
+    fn get_bandwidth(&self) -> u32 {
+        self.bandwidth
+    }
+
+    fn pll_locked(&self) -> Option<bool> {
+        Some(self.has_lock)
+    }
+
+    fn get_lo_freq(&self) -> Option<LoFreq> {
+        self.lo
+    }
+
+    fn freq_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        vec![E4K_FREQ_MIN.into()..=E4K_FREQ_MAX.into()]
+    }
+
+    fn get_if_freq(&self) -> Result<u32> {
+        // Zero-IF
+        Ok(0)
+    }
+
+    fn get_xtal_freq(&self) -> Result<u32> {
+        Ok(self.fosc)
+    }
+
+    fn set_xtal_freq(&mut self, freq: u32) -> Result<()> {
+        self.fosc = freq;
+        Ok(())
+    }
+
+    // e4k_standby
+    fn exit(&mut self, handle: &Device) -> Result<()> {
+        self.write_reg_mask(handle, REG_MASTER1, 0, MASTER1_NORM_STBY)
+    }
+}
+
+impl E4k {
+    /// Undocumented register values from the reference driver
+    fn magic_init(&self, handle: &Device) -> Result<()> {
+        self.write_reg(handle, 0x7e, 0x01)?;
+        self.write_reg(handle, 0x7f, 0xfe)?;
+        self.write_reg(handle, 0x82, 0x00)?;
+        // Polarity A
+        self.write_reg(handle, 0x86, 0x50)?;
+        self.write_reg(handle, 0x87, 0x20)?;
+        self.write_reg(handle, 0x88, 0x01)?;
+        self.write_reg(handle, 0x9f, 0x7f)?;
+        self.write_reg(handle, 0xa0, 0x07)?;
+        Ok(())
+    }
+
+    fn band_set(&self, handle: &Device, band: Band) -> Result<()> {
+        let bias = if band == Band::L { 0 } else { 3 };
+        self.write_reg(handle, REG_BIAS, bias)?;
+
+        // Workaround: without resetting the band first there is a gap between 325-350 MHz
+        self.write_reg_mask(handle, REG_SYNTH1, 0, 0x06)?;
+        self.write_reg_mask(handle, REG_SYNTH1, (band as u8) << 1, 0x06)
+    }
+
+    fn enable_manual_gain(&self, handle: &Device, manual: bool) -> Result<()> {
+        if manual {
+            // LNA and mixer gain under manual control
+            self.write_reg_mask(handle, REG_AGC1, AGC_MOD_SERIAL, AGC1_MOD_MASK)?;
+            self.write_reg_mask(handle, REG_AGC7, 0, AGC7_MIX_GAIN_AUTO)?;
+        } else {
+            self.write_reg_mask(handle, REG_AGC1, AGC_MOD_IF_SERIAL_LNA_AUTON, AGC1_MOD_MASK)?;
+            self.write_reg_mask(handle, REG_AGC7, AGC7_MIX_GAIN_AUTO, AGC7_MIX_GAIN_AUTO)?;
+            // Disable LNA gain enhancement
+            self.write_reg_mask(handle, REG_AGC11, 0, 0x07)?;
+        }
+        Ok(())
+    }
+
+    /// LNA gain in tenths of a dB
+    fn set_lna_gain(&self, handle: &Device, gain: i32) -> Result<()> {
+        match LNA_GAINS.iter().find(|(g, _)| *g == gain) {
+            Some((_, val)) => self.write_reg_mask(handle, REG_GAIN1, *val, 0x0f),
+            None => Err(InvalidGain {
+                stage: GainStage::Lna,
+                gain,
+                supported: LNA_GAINS.iter().map(|(g, _)| *g).collect(),
+            }),
+        }
+    }
+
+    /// Mixer gain in dB, either 4 or 12
+    fn mixer_gain_set(&self, handle: &Device, gain: i32) -> Result<()> {
+        let bit = match gain {
+            4 => 0,
+            12 => 1,
+            _ => {
+                return Err(InvalidGain {
+                    stage: GainStage::Mixer,
+                    gain: gain * 10,
+                    supported: vec![40, 120],
+                })
+            }
+        };
+        self.write_reg_mask(handle, REG_GAIN2, bit, 0x01)
+    }
+
+    /// Gain of IF stage 1-6 in dB
+    fn if_gain_set(&self, handle: &Device, stage: i32, gain: i32) -> Result<()> {
+        let index = usize::try_from(stage - 1)
+            .ok()
+            .filter(|i| *i < IF_STAGE_GAINS.len())
+            .ok_or(InvalidIfStage {
+                stage,
+                supported: 1..=IF_STAGE_GAINS.len() as i32,
+            })?;
+        let gains = IF_STAGE_GAINS[index];
+        let val = gains
+            .iter()
+            .position(|g| *g == gain)
+            .ok_or_else(|| InvalidIfGain {
+                stage,
+                gain: gain * 10,
+                supported: gains.iter().map(|g| g * 10).collect(),
+            })?;
+        let field = &IF_STAGE_FIELDS[index];
+        self.write_reg_mask(handle, field.reg, (val as u8) << field.shift, field.mask())
+    }
+
+    fn if_filter_bw_set(&self, handle: &Device, filter: IfFilter, bw: u32) -> Result<()> {
+        let index = closest_index(filter.bandwidths(), bw) as u8;
+        let field = filter.field();
+        self.write_reg_mask(handle, field.reg, index << field.shift, field.mask())
+    }
+
+    /// Measure the DC offset for each mixer and IF stage 1 gain combination and store the
+    /// results in the lookup tables the tuner corrects from (e4k_dc_offset_gen_table)
+    fn dc_offset_gen_table(&self, handle: &Device) -> Result<()> {
+        // LNA, mixer and IF gain to full manual
+        self.enable_manual_gain(handle, true)?;
+
+        // Set all other IF gains to maximum
+        for stage in 2..=6 {
+            self.if_gain_set(handle, stage, IF_STAGE_MAX[stage as usize - 1])?;
+        }
+
+        for (i, comb) in DC_GAIN_COMBS.iter().enumerate() {
+            self.mixer_gain_set(handle, comb.mixer_gain)?;
+            self.if_gain_set(handle, 1, comb.if1_gain)?;
+
+            // Make sure the DC range detector is enabled, then calibrate
+            self.write_reg_mask(handle, REG_DC5, DC5_RANGE_DET_EN, DC5_RANGE_DET_EN)?;
+            self.write_reg(handle, REG_DC1, DC1_CAL_REQ)?;
+
+            let offs_i = self.read_reg(handle, REG_DC2)? & 0x3f;
+            let offs_q = self.read_reg(handle, REG_DC3)? & 0x3f;
+            let range = self.read_reg(handle, REG_DC4)?;
+            let range_i = range & 0x03;
+            let range_q = (range >> 4) & 0x03;
+            info!(
+                "[E4K] DC offset table {} I={}/{}, Q={}/{}",
+                i, range_i, offs_i, range_q, offs_q
+            );
+
+            self.write_reg(handle, comb.reg, offs_q | (range_q << 6))?;
+            self.write_reg(
+                handle,
+                comb.reg - REG_QLUT0 + REG_ILUT0,
+                offs_i | (range_i << 6),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn read_reg(&self, handle: &Device, reg: u8) -> Result<u8> {
+        handle.i2c_read_reg(self.info.i2c_addr, reg)
+    }
+
+    fn write_reg(&self, handle: &Device, reg: u8, val: u8) -> Result<()> {
+        handle.i2c_write(self.info.i2c_addr.into(), &[reg, val])?;
+        Ok(())
+    }
+
+    /// Write only the bits in `mask`, skipping the write if they already hold `val`
+    fn write_reg_mask(&self, handle: &Device, reg: u8, val: u8, bit_mask: u8) -> Result<()> {
+        let tmp = self.read_reg(handle, reg)?;
+        if tmp & bit_mask == val & bit_mask {
+            return Ok(());
+        }
+        self.write_reg(handle, reg, (tmp & !bit_mask) | (val & bit_mask))
+    }
+}
@@ -0,0 +1,35 @@
+use super::e4k::{compute_pll_params, rf_filter_index};
+
+#[test]
+fn test_pll_params_vhf() {
+    let p = compute_pll_params(28_800_000, 100_000_000).unwrap();
+    // 3-phase mixing with R = 32
+    assert_eq!(p.r_idx, 0x08 | 5);
+    assert_eq!(p.r, 32);
+    assert_eq!(p.z, 111);
+    assert_eq!(p.x, 7281);
+    assert_eq!(p.flo, 99_999_989);
+}
+
+#[test]
+fn test_pll_params_l_band() {
+    let p = compute_pll_params(28_800_000, 1_500_000_000).unwrap();
+    // Above the table the divider stays at 2 with 2-phase mixing
+    assert_eq!(p.r_idx, 0);
+    assert_eq!(p.r, 2);
+    assert_eq!(p.z, 104);
+    assert_eq!(p.x, 10922);
+    assert!(p.flo.abs_diff(1_500_000_000) < 1000);
+}
+
+#[test]
+fn test_pll_params_invalid_xtal() {
+    assert_eq!(compute_pll_params(40_000_000, 100_000_000), None);
+}
+
+#[test]
+fn test_rf_filter() {
+    assert_eq!(rf_filter_index(100_000_000), 0);
+    assert_eq!(rf_filter_index(433_920_000), 3);
+    assert_eq!(rf_filter_index(1_575_420_000), 9);
+}
@@ -0,0 +1,537 @@
+use super::{LoFreq, Tuner, TunerGain, TunerInfo};
+use crate::device::Device;
+use crate::error::Result;
+use log::info;
+use std::ops::RangeInclusive;
+
+const FC001X_I2C_ADDR: u8 = 0xc6;
+const FC001X_CHECK_ADDR: u8 = 0x00;
+const FC0012_CHECK_VAL: u8 = 0xa1;
+const FC0013_CHECK_VAL: u8 = 0xa3;
+
+// GPIO switching the FC0012's external V/UHF filter
+const FC0012_VUHF_GPIO: u8 = 6;
+const FC001X_UHF_FREQ: u32 = 300_000_000;
+// Highest VCO frequency of the low range, above which the high range is selected
+const FC001X_VCO_HIGH_FREQ: u64 = 3_060_000_000;
+const FC001X_FREQ_MIN: u32 = 22_000_000;
+const FC0012_FREQ_MAX: u32 = 948_600_000;
+const FC0013_FREQ_MAX: u32 = 1_100_000_000;
+
+pub const FC0012_GAINS: [i32; 5] = [-99, -40, 71, 179, 192];
+pub const FC0013_GAINS: [i32; 23] = [
+    -99, -73, -65, -63, -60, -58, -54, 58, 61, 63, 65, 67, 68, 70, 71, 179, 181, 182, 184, 186,
+    188, 191, 197,
+];
+
+// LNA gain in tenths of a dB and the register bits selecting it
+const FC0012_LNA_GAINS: [(i32, u8); 5] = [
+    (-99, 0x02),
+    (-40, 0x00),
+    (71, 0x08),
+    (179, 0x17),
+    (192, 0x10),
+];
+const FC0013_LNA_GAINS: [(i32, u8); 24] = [
+    (-99, 0x02),
+    (-73, 0x03),
+    (-65, 0x05),
+    (-63, 0x04),
+    (-63, 0x00),
+    (-60, 0x07),
+    (-58, 0x01),
+    (-54, 0x06),
+    (58, 0x0f),
+    (61, 0x0e),
+    (63, 0x0d),
+    (65, 0x0c),
+    (67, 0x0b),
+    (68, 0x0a),
+    (70, 0x09),
+    (71, 0x08),
+    (179, 0x17),
+    (181, 0x16),
+    (182, 0x15),
+    (184, 0x14),
+    (186, 0x13),
+    (188, 0x12),
+    (191, 0x11),
+    (197, 0x10),
+];
+
+// Init registers 0x01 to 0x15
+const FC0012_REG_INIT: [u8; 21] = [
+    0x05, // 0x01
+    0x10, // 0x02
+    0x00, // 0x03
+    0x00, // 0x04
+    0x0f, // 0x05: may also be 0x0a
+    0x00, // 0x06: divider 2, VCO slow
+    0x20, // 0x07: 28.8 MHz crystal, may also be 0x0f
+    0xff, // 0x08: AGC clock divide by 256, AGC gain 1/256, loop bw 1/8
+    0x6e, // 0x09: disable loop through, enable loop through: 0x6f
+    0xb8, // 0x0a: disable LO test buffer
+    0x82, // 0x0b: output clock is same as clock frequency, may also be 0x83
+    0xfe, // 0x0c: depending on AGC up-down mode, may need 0xf8. Dual master.
+    0x02, // 0x0d: AGC not forcing & LNA forcing, 0x02 for DVB-T
+    0x00, // 0x0e
+    0x00, // 0x0f
+    0x00, // 0x10: may also be 0x0d
+    0x00, // 0x11
+    0x1f, // 0x12: set to maximum gain
+    0x08, // 0x13: middle gain: 0x08, low gain: 0x00, high gain: 0x10, enable IX2: 0x80
+    0x00, // 0x14
+    0x04, // 0x15: enable LNA COMPSATE
+];
+const FC0013_REG_INIT: [u8; 21] = [
+    0x09, // 0x01
+    0x16, // 0x02
+    0x00, // 0x03
+    0x00, // 0x04
+    0x17, // 0x05
+    0x02, // 0x06
+    0x2a, // 0x07: 28.8 MHz crystal
+    0xff, // 0x08: AGC clock divide by 256, AGC gain 1/256, loop bw 1/8
+    0x6f, // 0x09: enable loop through
+    0xb8, // 0x0a: disable LO test buffer
+    0x82, // 0x0b
+    0xfe, // 0x0c: depending on AGC up-down mode, may need 0xf8. Dual master.
+    0x01, // 0x0d: AGC not forcing & LNA forcing, may need 0x02
+    0x00, // 0x0e
+    0x00, // 0x0f
+    0x00, // 0x10
+    0x00, // 0x11
+    0x00, // 0x12
+    0x00, // 0x13
+    0x50, // 0x14: DVB-T high gain, UHF. Middle gain: 0x48, low gain: 0x40
+    0x01, // 0x15
+];
+
+// RF divider choice: upper frequency, divider, and register 5 and 6 values. Keeps
+// freq * multi below 3.56 GHz.
+const FC0012_DIVIDERS: [(u32, u32, u8, u8); 10] = [
+    (37_084_000, 96, 0x82, 0x00),
+    (55_625_000, 64, 0x82, 0x02),
+    (74_167_000, 48, 0x42, 0x00),
+    (111_250_000, 32, 0x42, 0x02),
+    (148_334_000, 24, 0x22, 0x00),
+    (222_500_000, 16, 0x22, 0x02),
+    (296_667_000, 12, 0x12, 0x00),
+    (445_000_000, 8, 0x12, 0x02),
+    (593_334_000, 6, 0x0a, 0x00),
+    (u32::MAX, 4, 0x0a, 0x02),
+];
+const FC0013_DIVIDERS: [(u32, u32, u8, u8); 11] = [
+    (37_084_000, 96, 0x82, 0x00),
+    (55_625_000, 64, 0x02, 0x02),
+    (74_167_000, 48, 0x42, 0x00),
+    (111_250_000, 32, 0x82, 0x02),
+    (148_334_000, 24, 0x22, 0x00),
+    (222_500_000, 16, 0x42, 0x02),
+    (296_667_000, 12, 0x12, 0x00),
+    (445_000_000, 8, 0x22, 0x02),
+    (593_334_000, 6, 0x0a, 0x00),
+    // Keep freq * multi below 3.8 GHz
+    (950_000_000, 4, 0x12, 0x02),
+    (u32::MAX, 2, 0x0a, 0x02),
+];
+
+// FC0013 VHF tracking filter setting for frequencies up to each limit
+const FC0013_VHF_TRACK: [(u32, u8); 7] = [
+    (177_500_000, 0x1c),
+    (184_500_000, 0x18),
+    (191_500_000, 0x14),
+    (198_500_000, 0x10),
+    (205_500_000, 0x0c),
+    (219_500_000, 0x08),
+    (FC001X_UHF_FREQ - 1, 0x04),
+];
+
+/// Register values that tune the PLL to one frequency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PllSettings {
+    /// Registers 0x01 to 0x06
+    pub regs: [u8; 6],
+    pub multi: u32,
+    /// VCO high range selected
+    pub vco_select: bool,
+    /// LO frequency the registers actually produce
+    pub lo_freq: f64,
+}
+
+/// Compute the PLL registers for `freq`, shared by both chips (fc001x_set_params).
+/// Returns `None` when the dividers can't reach the frequency.
+pub(crate) fn compute_pll(
+    fc0013: bool,
+    xtal: u32,
+    freq: u32,
+    bandwidth: u32,
+) -> Option<PllSettings> {
+    let xtal_freq_div_2 = (xtal / 2) as u64;
+    let dividers: &[(u32, u32, u8, u8)] = if fc0013 {
+        &FC0013_DIVIDERS
+    } else {
+        &FC0012_DIVIDERS
+    };
+    let (_, multi, reg5, reg6) = *dividers.iter().find(|(max, _, _, _)| freq < *max)?;
+    let mut reg = [0u8; 7];
+    reg[5] = reg5;
+    reg[6] = reg6;
+
+    let f_vco = freq as u64 * multi as u64;
+    let vco_select = f_vco >= FC001X_VCO_HIGH_FREQ;
+    if vco_select {
+        reg[6] |= 0x08;
+    }
+
+    // From divided value (XDIV) determine the FA and FP value
+    let mut xdiv = f_vco / xtal_freq_div_2;
+    if f_vco - xdiv * xtal_freq_div_2 >= xtal_freq_div_2 / 2 {
+        xdiv += 1;
+    }
+    let mut pm = xdiv / 8;
+    let mut am = xdiv - 8 * pm;
+    if am < 2 {
+        am += 8;
+        pm = pm.checked_sub(1)?;
+    }
+    if pm > 31 {
+        reg[1] = u8::try_from(am + 8 * (pm - 31)).ok()?;
+        reg[2] = 31;
+    } else {
+        reg[1] = am as u8;
+        reg[2] = pm as u8;
+    }
+    if reg[1] > 15 || reg[2] < 0x0b {
+        return None;
+    }
+
+    // Fix clock out
+    reg[6] |= 0x20;
+
+    // From VCO frequency determine the XIN (fractional part of the delta sigma PLL)
+    let xin = (f_vco - (f_vco / xtal_freq_div_2) * xtal_freq_div_2) / 1000;
+    let mut xin = ((xin << 15) / (xtal_freq_div_2 / 1000)) as u16;
+    if xin >= 16384 {
+        xin += 32768;
+    }
+    reg[3] = (xin >> 8) as u8;
+    reg[4] = xin as u8;
+
+    // Bits 6 and 7 select the bandwidth
+    reg[6] &= 0x3f;
+    reg[6] |= match bandwidth {
+        6_000_000 => 0x80,
+        7_000_000 => 0x40,
+        _ => 0x00,
+    };
+
+    // Modified for Realtek demod
+    reg[5] |= 0x07;
+
+    // XIN is signed relative to the rounded XDIV
+    let fvco = xtal_freq_div_2 as f64 * (xdiv as f64 + xin as i16 as f64 / 32768.0);
+
+    let mut regs = [0; 6];
+    regs.copy_from_slice(&reg[1..]);
+    Some(PllSettings {
+        regs,
+        multi,
+        vco_select,
+        lo_freq: fvco / multi as f64,
+    })
+}
+
+const BANDWIDTHS: [u32; 3] = [6_000_000, 7_000_000, 8_000_000];
+
+/// Channel bandwidth closest to `bw` that the chip supports, 6, 7 or 8 MHz
+fn supported_bandwidth(bw: u32) -> u32 {
+    BANDWIDTHS
+        .into_iter()
+        .find(|supported| bw <= *supported)
+        .unwrap_or(BANDWIDTHS[2])
+}
+
+pub const FC0012_TUNER_ID: &str = "fc0012";
+
+pub const FC0012_TUNER_INFO: TunerInfo = TunerInfo {
+    id: FC0012_TUNER_ID,
+    name: "Fitipower FC0012",
+    i2c_addr: FC001X_I2C_ADDR,
+    check_addr: FC001X_CHECK_ADDR,
+    check_val: FC0012_CHECK_VAL,
+    check_mask: 0xff,
+    probe_reset: true,
+};
+
+pub const FC0013_TUNER_ID: &str = "fc0013";
+
+pub const FC0013_TUNER_INFO: TunerInfo = TunerInfo {
+    id: FC0013_TUNER_ID,
+    name: "Fitipower FC0013",
+    i2c_addr: FC001X_I2C_ADDR,
+    check_addr: FC001X_CHECK_ADDR,
+    check_val: FC0013_CHECK_VAL,
+    check_mask: 0xff,
+    probe_reset: false,
+};
+
+/// Driver for the Fitipower FC0012 and FC0013, zero-IF tuners sharing most of their design
+#[derive(Debug)]
+pub struct Fc001x {
+    pub info: TunerInfo,
+    is_fc0013: bool,
+    xtal: u32,
+    freq: u32,
+    bandwidth: u32,
+    lo: Option<LoFreq>,
+}
+
+impl Fc001x {
+    pub fn new_fc0012(_handle: &mut Device) -> Fc001x {
+        Fc001x::with_info(FC0012_TUNER_INFO)
+    }
+
+    pub fn new_fc0013(_handle: &mut Device) -> Fc001x {
+        Fc001x::with_info(FC0013_TUNER_INFO)
+    }
+
+    fn with_info(info: TunerInfo) -> Fc001x {
+        Fc001x {
+            info,
+            is_fc0013: info.id == FC0013_TUNER_ID,
+            xtal: 0,
+            freq: 0,
+            bandwidth: 6_000_000,
+            lo: None,
+        }
+    }
+
+    fn lna_gains(&self) -> &'static [(i32, u8)] {
+        if self.is_fc0013 {
+            &FC0013_LNA_GAINS
+        } else {
+            &FC0012_LNA_GAINS
+        }
+    }
+
+    // LNA gain lives in a different register on each chip
+    fn lna_gain_reg(&self) -> u8 {
+        if self.is_fc0013 {
+            0x14
+        } else {
+            0x13
+        }
+    }
+}
+
+impl Tuner for Fc001x {
+    fn init(&mut self, handle: &Device) -> Result<()> {
+        let reg_init = if self.is_fc0013 {
+            &FC0013_REG_INIT
+        } else {
+            &FC0012_REG_INIT
+        };
+        for (i, val) in reg_init.iter().enumerate() {
+            self.write_reg(handle, i as u8 + 1, *val)?;
+        }
+        if !self.is_fc0013 {
+            handle.set_gpio_output(FC0012_VUHF_GPIO)?;
+        }
+        self.freq = 0;
+        self.lo = None;
+        Ok(())
+    }
+
+    fn get_info(&self) -> Result<TunerInfo> {
+        Ok(self.info)
+    }
+
+    fn get_gains(&self) -> Result<Vec<i32>> {
+        if self.is_fc0013 {
+            Ok(FC0013_GAINS.to_vec())
+        } else {
+            Ok(FC0012_GAINS.to_vec())
+        }
+    }
+
+    fn read_gain(&self, handle: &Device) -> Result<i32> {
+        let val = self.read_reg(handle, self.lna_gain_reg())? & 0x1f;
+        Ok(self
+            .lna_gains()
+            .iter()
+            .find(|(_, bits)| *bits == val)
+            .map_or(0, |(gain, _)| *gain))
+    }
+
+    fn set_gain(&mut self, handle: &Device, gain: TunerGain) -> Result<()> {
+        // The FC0012 has no gain mode switch, its LNA gain is always manual
+        if self.is_fc0013 {
+            // fc0013_set_gain_mode
+            let manual = matches!(gain, TunerGain::Manual(_));
+            let tmp = self.read_reg(handle, 0x0d)?;
+            let tmp = if manual { tmp | 0x08 } else { tmp & !0x08 };
+            self.write_reg(handle, 0x0d, tmp)?;
+            // Set a fixed IF gain for now
+            self.write_reg(handle, 0x13, 0x0a)?;
+        }
+        let TunerGain::Manual(gain) = gain else {
+            return Ok(());
+        };
+        let gains = self.lna_gains();
+        let bits = if self.is_fc0013 {
+            // First step at or above the requested gain
+            gains
+                .iter()
+                .find(|(g, _)| *g >= gain)
+                .unwrap_or(&gains[gains.len() - 1])
+                .1
+        } else {
+            gains
+                .iter()
+                .min_by_key(|(g, _)| g.abs_diff(gain))
+                .expect("gain table is not empty")
+                .1
+        };
+        let reg = self.lna_gain_reg();
+        let tmp = self.read_reg(handle, reg)? & 0xe0;
+        self.write_reg(handle, reg, tmp | bits)
+    }
+
+    fn set_freq(&mut self, handle: &Device, freq: u32) -> Result<()> {
+        let pll = compute_pll(self.is_fc0013, self.xtal, freq, self.bandwidth)
+            .ok_or_else(|| self.out_of_range(freq))?;
+        info!("[FC001X] PLL settings for {} Hz: {:?}", freq, pll);
+
+        if self.is_fc0013 {
+            self.set_fc0013_band(handle, freq)?;
+        } else {
+            // Select the V-band/U-band filter
+            handle.set_gpio_bit(FC0012_VUHF_GPIO, freq > FC001X_UHF_FREQ)?;
+        }
+
+        for (i, val) in pll.regs.iter().enumerate() {
+            self.write_reg(handle, i as u8 + 1, *val)?;
+        }
+        self.lo = Some(LoFreq {
+            requested: freq,
+            actual: pll.lo_freq,
+        });
+        if self.is_fc0013 {
+            let tmp = self.read_reg(handle, 0x11)?;
+            let tmp = if pll.multi == 64 {
+                tmp | 0x04
+            } else {
+                tmp & 0xfb
+            };
+            self.write_reg(handle, 0x11, tmp)?;
+        }
+
+        // VCO calibration, then re-calibration if needed
+        self.calibrate_vco(handle)?;
+        self.write_reg(handle, 0x0e, 0x00)?;
+        let voltage = self.read_reg(handle, 0x0e)? & 0x3f;
+
+        // Switch VCO range if the control voltage is at the end of the current one
+        let mut reg6 = pll.regs[5];
+        if pll.vco_select && voltage > 0x3c {
+            reg6 &= !0x08;
+        } else if !pll.vco_select && voltage < 0x02 {
+            reg6 |= 0x08;
+        }
+        if reg6 != pll.regs[5] {
+            self.write_reg(handle, 0x06, reg6)?;
+            self.calibrate_vco(handle)?;
+        }
+        self.freq = freq;
+        Ok(())
+    }
+
+    fn set_bandwidth(&mut self, handle: &Device, bw: u32, _rate: u32) -> Result<()> {
+        // Bandwidth is programmed along with the PLL, so retune to apply it
+        self.bandwidth = supported_bandwidth(bw);
+        if self.freq != 0 {
+            self.set_freq(handle, self.freq)?;
+        }
+        Ok(())
+    }
+
+    fn get_bandwidths(&self) -> Vec<u32> {
+        BANDWIDTHS.to_vec()
+    }
+
+    fn get_bandwidth(&self) -> u32 {
+        self.bandwidth
+    }
+
+    fn get_lo_freq(&self) -> Option<LoFreq> {
+        self.lo
+    }
+
+    fn freq_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        let max = if self.is_fc0013 {
+            FC0013_FREQ_MAX
+        } else {
+            FC0012_FREQ_MAX
+        };
+        vec![FC001X_FREQ_MIN.into()..=max.into()]
+    }
+
+    fn get_if_freq(&self) -> Result<u32> {
+        // Zero-IF
+        Ok(0)
+    }
+
+    fn get_xtal_freq(&self) -> Result<u32> {
+        Ok(self.xtal)
+    }
+
+    fn set_xtal_freq(&mut self, freq: u32) -> Result<()> {
+        self.xtal = freq;
+        Ok(())
+    }
+
+    fn exit(&mut self, _handle: &Device) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Fc001x {
+    /// Select the FC0013's VHF tracking filter and its VHF, UHF or GPS input
+    fn set_fc0013_band(&self, handle: &Device, freq: u32) -> Result<()> {
+        // UHF and GPS use the same setting as the lowest VHF range
+        let track = FC0013_VHF_TRACK
+            .iter()
+            .find(|(max, _)| freq <= *max)
+            .map_or(0x1c, |(_, val)| *val);
+        let tmp = self.read_reg(handle, 0x1d)? & 0xe3;
+        self.write_reg(handle, 0x1d, tmp | track)?;
+
+        let filt = self.read_reg(handle, 0x07)?;
+        let band = self.read_reg(handle, 0x14)? & 0x1f;
+        if freq < FC001X_UHF_FREQ {
+            // Enable VHF filter, disable UHF and GPS
+            self.write_reg(handle, 0x07, filt | 0x10)?;
+            self.write_reg(handle, 0x14, band)?;
+        } else {
+            // Disable VHF filter, enable UHF and disable GPS
+            self.write_reg(handle, 0x07, filt & 0xef)?;
+            self.write_reg(handle, 0x14, band | 0x40)?;
+        }
+        Ok(())
+    }
+
+    fn calibrate_vco(&self, handle: &Device) -> Result<()> {
+        self.write_reg(handle, 0x0e, 0x80)?;
+        self.write_reg(handle, 0x0e, 0x00)
+    }
+
+    fn read_reg(&self, handle: &Device, reg: u8) -> Result<u8> {
+        handle.i2c_read_reg(self.info.i2c_addr, reg)
+    }
+
+    fn write_reg(&self, handle: &Device, reg: u8, val: u8) -> Result<()> {
+        handle.i2c_write(self.info.i2c_addr.into(), &[reg, val])?;
+        Ok(())
+    }
+}
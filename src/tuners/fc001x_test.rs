@@ -0,0 +1,28 @@
+use super::fc001x::compute_pll;
+
+#[test]
+fn test_fc0012_pll() {
+    let pll = compute_pll(false, 28_800_000, 100_000_000, 6_000_000).unwrap();
+    assert_eq!(pll.multi, 32);
+    // 3.2 GHz is in the high VCO range
+    assert!(pll.vco_select);
+    assert_eq!(pll.regs, [6, 27, 0x1c, 0x71, 0x47, 0xaa]);
+    assert!((pll.lo_freq - 99_999_989.3).abs() < 0.1);
+}
+
+#[test]
+fn test_fc0013_pll() {
+    let pll = compute_pll(true, 28_800_000, 1_090_000_000, 8_000_000).unwrap();
+    assert_eq!(pll.multi, 2);
+    assert!(!pll.vco_select);
+    assert_eq!(pll.regs, [7, 18, 0x31, 0xc7, 0x0f, 0x22]);
+}
+
+#[test]
+fn test_fc0012_out_of_range() {
+    // Only the FC0013 has a divider for L-band
+    assert_eq!(
+        compute_pll(false, 28_800_000, 1_090_000_000, 6_000_000),
+        None
+    );
+}
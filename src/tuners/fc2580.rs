@@ -0,0 +1,469 @@
+use std::ops::RangeInclusive;
+use std::thread;
+use std::time::Duration;
+
+use super::{LoFreq, Tuner, TunerGain, TunerInfo};
+use crate::device::Device;
+use crate::error::Result;
+use log::{info, warn};
+
+const FC2580_I2C_ADDR: u8 = 0xac;
+const FC2580_CHECK_ADDR: u8 = 0x01;
+const FC2580_CHECK_VAL: u8 = 0x56;
+/// Crystal on FC2580 dongles (at least the Logilink VG0002A)
+pub const FC2580_XTAL_FREQ: u32 = 16_384_000;
+
+// VCO frequency in kHz above which the high VCO band is selected
+const BORDER_FREQ: u32 = 2_600_000;
+// Use the internal crystal oscillator rather than an external clock
+const USE_EXT_CLK: u8 = 0;
+// Specified coverage, with a gap between VHF and UHF
+const FREQ_RANGES: [RangeInclusive<u64>; 2] =
+    [146_000_000..=308_000_000, 438_000_000..=924_000_000];
+const FILTER_CAL_RETRIES: usize = 5;
+const FILTER_CAL_WAIT: Duration = Duration::from_millis(5);
+
+/// The gain is fixed, under control of the demod's IF AGC
+pub const GAINS: [i32; 1] = [0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Band {
+    Vhf,
+    Uhf,
+    L,
+}
+
+impl Band {
+    fn for_freq(f_lo_khz: u32) -> Band {
+        if f_lo_khz > 1_000_000 {
+            Band::L
+        } else if f_lo_khz > 400_000 {
+            Band::Uhf
+        } else {
+            Band::Vhf
+        }
+    }
+
+    /// VCO to LO division ratio
+    fn lo_div(&self) -> u32 {
+        match self {
+            Band::Uhf => 4,
+            Band::L => 2,
+            Band::Vhf => 12,
+        }
+    }
+
+    /// Filter mode the reference driver uses for the band
+    fn default_filter(&self) -> FilterBw {
+        match self {
+            Band::Vhf => FilterBw::Bw6800k,
+            Band::Uhf => FilterBw::Bw7800k,
+            Band::L => FilterBw::Bw1530k,
+        }
+    }
+}
+
+/// Channel filter modes, named after their bandwidth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterBw {
+    Bw1530k,
+    Bw6000k,
+    Bw6800k,
+    Bw7800k,
+}
+
+impl FilterBw {
+    /// Narrowest filter at least `bw` wide
+    fn for_bandwidth(bw: u32) -> FilterBw {
+        FilterBw::ALL
+            .into_iter()
+            .find(|filter| bw <= filter.bandwidth())
+            .unwrap_or(FilterBw::Bw7800k)
+    }
+
+    const ALL: [FilterBw; 4] = [
+        FilterBw::Bw1530k,
+        FilterBw::Bw6000k,
+        FilterBw::Bw6800k,
+        FilterBw::Bw7800k,
+    ];
+
+    fn bandwidth(&self) -> u32 {
+        match self {
+            FilterBw::Bw1530k => 1_530_000,
+            FilterBw::Bw6000k => 6_000_000,
+            FilterBw::Bw6800k => 6_800_000,
+            FilterBw::Bw7800k => 7_800_000,
+        }
+    }
+
+    /// Register 0x36 and 0x39 values, and the calibration factor for register 0x37
+    fn settings(&self) -> (u8, u8, u32) {
+        match self {
+            FilterBw::Bw1530k => (0x1c, 0x00, 4151),
+            FilterBw::Bw6000k => (0x18, 0x00, 4400),
+            FilterBw::Bw6800k => (0x18, 0x80, 3910),
+            FilterBw::Bw7800k => (0x18, 0x80, 3300),
+        }
+    }
+}
+
+// Per-band LNA, mixer and AGC settings
+const UHF_REGS: [(u8, u8); 13] = [
+    (0x25, 0xf0),
+    (0x27, 0x77),
+    (0x28, 0x53),
+    (0x29, 0x60),
+    (0x30, 0x09),
+    (0x50, 0x8c),
+    (0x53, 0x50),
+    (0x63, 0x15),
+    (0x6b, 0x0b),
+    (0x6c, 0x0c),
+    (0x6d, 0x78),
+    (0x6e, 0x32),
+    (0x6f, 0x14),
+];
+const VHF_REGS: [(u8, u8); 19] = [
+    (0x27, 0x77),
+    (0x28, 0x33),
+    (0x29, 0x40),
+    (0x30, 0x09),
+    (0x50, 0x8c),
+    (0x53, 0x50),
+    (0x5f, 0x0f),
+    (0x61, 0x07),
+    (0x62, 0x00),
+    (0x63, 0x15),
+    (0x67, 0x03),
+    (0x68, 0x05),
+    (0x69, 0x10),
+    (0x6a, 0x12),
+    (0x6b, 0x08),
+    (0x6c, 0x0a),
+    (0x6d, 0x78),
+    (0x6e, 0x32),
+    (0x6f, 0x54),
+];
+const L_BAND_REGS: [(u8, u8); 20] = [
+    (0x2b, 0x70),
+    (0x2c, 0x37),
+    (0x2d, 0xe7),
+    (0x30, 0x09),
+    (0x44, 0x20),
+    (0x50, 0x8c),
+    (0x53, 0x50),
+    (0x5f, 0x0f),
+    (0x61, 0x0f),
+    (0x62, 0x00),
+    (0x63, 0x13),
+    (0x67, 0x00),
+    (0x68, 0x02),
+    (0x69, 0x0c),
+    (0x6a, 0x0e),
+    (0x6b, 0x08),
+    (0x6c, 0x0a),
+    (0x6d, 0xa0),
+    (0x6e, 0x50),
+    (0x6f, 0x14),
+];
+
+/// PLL settings for one LO frequency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PllSettings {
+    pub band: Band,
+    /// Reference divider, 1, 2 or 4
+    pub r_val: u32,
+    pub n_val: u8,
+    /// 20 bit fractional part
+    pub k_val: u32,
+    pub vco_high: bool,
+}
+
+impl PllSettings {
+    /// LO frequency the settings produce with a crystal of `xtal` Hz
+    pub fn lo_freq(&self, xtal: u32) -> f64 {
+        let f_comp = xtal as f64 / self.r_val as f64;
+        2.0 * f_comp * (self.n_val as f64 + self.k_val as f64 / (1 << 20) as f64)
+            / self.band.lo_div() as f64
+    }
+}
+
+/// Compute the PLL dividers, with frequencies in kHz as the reference driver uses
+/// (from fc2580_set_freq)
+pub(crate) fn compute_pll(xtal_khz: u32, f_lo_khz: u32) -> Option<PllSettings> {
+    const PRE_SHIFT_BITS: u32 = 4;
+    let band = Band::for_freq(f_lo_khz);
+    let f_vco = f_lo_khz as u64 * band.lo_div() as u64;
+    let xtal_khz = xtal_khz as u64;
+    let r_val = if f_vco >= 2 * 76 * xtal_khz {
+        1
+    } else if f_vco >= 76 * xtal_khz {
+        2
+    } else {
+        4
+    };
+    let f_comp = xtal_khz / r_val;
+    let n_val = u8::try_from((f_vco / 2) / f_comp).ok()?;
+
+    let f_diff = f_vco - 2 * f_comp * n_val as u64;
+    let f_diff_shifted = f_diff << (20 - PRE_SHIFT_BITS);
+    let divisor = (2 * f_comp) >> PRE_SHIFT_BITS;
+    let mut k_val = f_diff_shifted / divisor;
+    if f_diff_shifted - k_val * divisor >= f_comp >> PRE_SHIFT_BITS {
+        k_val += 1;
+    }
+
+    Some(PllSettings {
+        band,
+        r_val: r_val as u32,
+        n_val,
+        k_val: k_val as u32,
+        vco_high: f_vco >= BORDER_FREQ as u64,
+    })
+}
+
+pub const TUNER_ID: &str = "fc2580";
+
+pub const TUNER_INFO: TunerInfo = TunerInfo {
+    id: TUNER_ID,
+    name: "FCI FC2580",
+    i2c_addr: FC2580_I2C_ADDR,
+    check_addr: FC2580_CHECK_ADDR,
+    check_val: FC2580_CHECK_VAL,
+    check_mask: 0x7f,
+    probe_reset: true,
+};
+
+/// Driver for the FCI FC2580, a zero-IF tuner
+#[derive(Debug)]
+pub struct Fc2580 {
+    pub info: TunerInfo,
+    xtal: u32,
+    // Filter chosen through set_bandwidth, otherwise each band's default
+    filter: Option<FilterBw>,
+    // Filter currently set
+    applied_filter: FilterBw,
+    lo: Option<LoFreq>,
+}
+
+impl Fc2580 {
+    pub fn new(_handle: &mut Device) -> Fc2580 {
+        Fc2580 {
+            info: TUNER_INFO,
+            xtal: FC2580_XTAL_FREQ,
+            filter: None,
+            applied_filter: FilterBw::Bw7800k,
+            lo: None,
+        }
+    }
+
+    fn xtal_khz(&self) -> u32 {
+        (self.xtal + 500) / 1000
+    }
+}
+
+impl Tuner for Fc2580 {
+    // fc2580_set_init
+    fn init(&mut self, handle: &Device) -> Result<()> {
+        self.write_reg(handle, 0x00, 0x00)?;
+        self.write_reg(handle, 0x12, 0x86)?;
+        self.write_reg(handle, 0x14, 0x5c)?;
+        self.write_reg(handle, 0x16, 0x3c)?;
+        self.write_reg(handle, 0x1f, 0xd2)?;
+        self.write_reg(handle, 0x09, 0xd7)?;
+        self.write_reg(handle, 0x0b, 0xd5)?;
+        self.write_reg(handle, 0x0c, 0x32)?;
+        self.write_reg(handle, 0x0e, 0x43)?;
+        self.write_reg(handle, 0x21, 0x0a)?;
+        self.write_reg(handle, 0x22, 0x82)?;
+        // Voltage controlled gain from the demod's IF AGC, and HOLD_AGC polarity
+        self.write_reg(handle, 0x45, 0x20)?;
+        self.write_reg(handle, 0x4c, 0x02)?;
+        self.write_reg(handle, 0x3f, 0x88)?;
+        self.write_reg(handle, 0x02, 0x0e)?;
+        self.write_reg(handle, 0x58, 0x14)?;
+        self.filter = None;
+        self.lo = None;
+        self.set_filter(handle, FilterBw::Bw7800k)
+    }
+
+    fn get_info(&self) -> Result<TunerInfo> {
+        Ok(self.info)
+    }
+
+    fn get_gains(&self) -> Result<Vec<i32>> {
+        Ok(GAINS.to_vec())
+    }
+
+    fn read_gain(&self, _handle: &Device) -> Result<i32> {
+        Ok(0)
+    }
+
+    fn set_gain(&mut self, _handle: &Device, _gain: TunerGain) -> Result<()> {
+        // No manual gain control
+        Ok(())
+    }
+
+    // fc2580_set_freq
+    fn set_freq(&mut self, handle: &Device, freq: u32) -> Result<()> {
+        let f_lo = (freq + 500) / 1000;
+        let pll = compute_pll(self.xtal_khz(), f_lo).ok_or_else(|| self.out_of_range(freq))?;
+        info!("[FC2580] PLL settings for {} Hz: {:?}", freq, pll);
+
+        let mut data_0x02 = (USE_EXT_CLK << 5) | 0x0e;
+        if pll.vco_high {
+            data_0x02 |= 0x08;
+        } else {
+            data_0x02 &= 0xf7;
+        }
+        data_0x02 &= 0x3f;
+
+        match pll.band {
+            Band::Uhf => {
+                self.write_regs(handle, &UHF_REGS)?;
+                let (reg_0x5f, lna) = if f_lo < 538_000 {
+                    (0x13, [0x07, 0x06, 0x06, 0x08, 0x10, 0x12])
+                } else if f_lo < 794_000 {
+                    // ACI improve
+                    (0x15, [0x03, 0x03, 0x03, 0x05, 0x0c, 0x0e])
+                } else {
+                    (0x15, [0x07, 0x06, 0x07, 0x09, 0x10, 0x12])
+                };
+                self.write_reg(handle, 0x5f, reg_0x5f)?;
+                for (reg, val) in [0x61, 0x62, 0x67, 0x68, 0x69, 0x6a].into_iter().zip(lna) {
+                    self.write_reg(handle, reg, val)?;
+                }
+            }
+            Band::Vhf => {
+                data_0x02 |= 0x80;
+                self.write_regs(handle, &VHF_REGS)?;
+            }
+            Band::L => {
+                data_0x02 |= 0x40;
+                self.write_regs(handle, &L_BAND_REGS)?;
+            }
+        }
+        let filter = self.filter.unwrap_or(pll.band.default_filter());
+        self.set_filter(handle, filter)?;
+
+        // AGC clock pre-divide ratio
+        if self.xtal_khz() >= 28_000 {
+            self.write_reg(handle, 0x4b, 0x22)?;
+        }
+
+        // VCO band and PLL setting
+        self.write_reg(handle, 0x02, data_0x02)?;
+        let r_bits = match pll.r_val {
+            1 => 0x00,
+            2 => 0x10,
+            _ => 0x20,
+        };
+        self.write_reg(handle, 0x18, r_bits + (pll.k_val >> 16) as u8)?;
+        self.write_reg(handle, 0x1a, (pll.k_val >> 8) as u8)?;
+        self.write_reg(handle, 0x1b, pll.k_val as u8)?;
+        self.write_reg(handle, 0x1c, pll.n_val)?;
+        self.lo = Some(LoFreq {
+            requested: freq,
+            actual: pll.lo_freq(self.xtal),
+        });
+
+        // UHF LNA load cap
+        if pll.band == Band::Uhf {
+            let cap = if f_lo <= 794_000 { 0x9f } else { 0x8f };
+            self.write_reg(handle, 0x2d, cap)?;
+        }
+        Ok(())
+    }
+
+    fn set_bandwidth(&mut self, handle: &Device, bw: u32, _rate: u32) -> Result<()> {
+        let filter = FilterBw::for_bandwidth(bw);
+        self.filter = Some(filter);
+        self.set_filter(handle, filter)
+    }
+
+    fn get_bandwidths(&self) -> Vec<u32> {
+        FilterBw::ALL
+            .iter()
+            .map(|filter| filter.bandwidth())
+            .collect()
+    }
+
+    fn get_bandwidth(&self) -> u32 {
+        self.applied_filter.bandwidth()
+    }
+
+    fn get_lo_freq(&self) -> Option<LoFreq> {
+        self.lo
+    }
+
+    fn freq_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        FREQ_RANGES.to_vec()
+    }
+
+    fn get_if_freq(&self) -> Result<u32> {
+        // Zero-IF
+        Ok(0)
+    }
+
+    fn get_xtal_freq(&self) -> Result<u32> {
+        Ok(self.xtal)
+    }
+
+    fn set_xtal_freq(&mut self, freq: u32) -> Result<()> {
+        self.xtal = freq;
+        Ok(())
+    }
+
+    fn exit(&mut self, _handle: &Device) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Fc2580 {
+    /// Select the channel filter and run its calibration (fc2580_set_filter)
+    fn set_filter(&mut self, handle: &Device, filter: FilterBw) -> Result<()> {
+        self.applied_filter = filter;
+        let (reg_0x36, reg_0x39, cal_factor) = filter.settings();
+        self.write_reg(handle, 0x36, reg_0x36)?;
+        self.write_reg(
+            handle,
+            0x37,
+            (cal_factor * self.xtal_khz() / 1_000_000) as u8,
+        )?;
+        self.write_reg(handle, 0x39, reg_0x39)?;
+        self.write_reg(handle, 0x2e, 0x09)?;
+
+        let mut calibrated = false;
+        for _ in 0..FILTER_CAL_RETRIES {
+            thread::sleep(FILTER_CAL_WAIT);
+            if self.read_reg(handle, 0x2f)? & 0xc0 == 0xc0 {
+                calibrated = true;
+                break;
+            }
+            // Restart calibration
+            self.write_reg(handle, 0x2e, 0x01)?;
+            self.write_reg(handle, 0x2e, 0x09)?;
+        }
+        if !calibrated {
+            warn!("[FC2580] Filter calibration did not complete");
+        }
+        self.write_reg(handle, 0x2e, 0x01)
+    }
+
+    fn read_reg(&self, handle: &Device, reg: u8) -> Result<u8> {
+        handle.i2c_read_reg(self.info.i2c_addr, reg)
+    }
+
+    fn write_reg(&self, handle: &Device, reg: u8, val: u8) -> Result<()> {
+        handle.i2c_write(self.info.i2c_addr.into(), &[reg, val])?;
+        Ok(())
+    }
+
+    fn write_regs(&self, handle: &Device, regs: &[(u8, u8)]) -> Result<()> {
+        for (reg, val) in regs {
+            self.write_reg(handle, *reg, *val)?;
+        }
+        Ok(())
+    }
+}
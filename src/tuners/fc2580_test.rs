@@ -0,0 +1,32 @@
+use super::fc2580::{compute_pll, Band};
+
+#[test]
+fn test_vhf_pll() {
+    let pll = compute_pll(16_384, 100_000).unwrap();
+    assert_eq!(pll.band, Band::Vhf);
+    // 1.2 GHz VCO, below the comparison frequency thresholds
+    assert_eq!(pll.r_val, 4);
+    assert_eq!(pll.n_val, 146);
+    assert_eq!(pll.k_val, 0x7c000);
+    assert!(!pll.vco_high);
+    assert_eq!(pll.lo_freq(16_384_000), 100_000_000.0);
+}
+
+#[test]
+fn test_uhf_pll() {
+    let pll = compute_pll(16_384, 600_000).unwrap();
+    assert_eq!(pll.band, Band::Uhf);
+    assert_eq!(pll.r_val, 2);
+    assert_eq!(pll.n_val, 146);
+    assert_eq!(pll.k_val, 0x7c000);
+    assert!(!pll.vco_high);
+}
+
+#[test]
+fn test_l_band_pll() {
+    let pll = compute_pll(16_384, 1_575_420).unwrap();
+    assert_eq!(pll.band, Band::L);
+    // 3.15 GHz VCO uses the high VCO band
+    assert_eq!(pll.r_val, 1);
+    assert!(pll.vco_high);
+}
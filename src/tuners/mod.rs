@@ -1,9 +1,51 @@
+pub mod e4k;
+#[cfg(test)]
+mod e4k_test;
+pub mod fc001x;
+#[cfg(test)]
+mod fc001x_test;
+pub mod fc2580;
+#[cfg(test)]
+mod fc2580_test;
 pub mod r820t;
 use crate::device::Device;
 use crate::error::Result;
-use crate::TunerGain;
+use crate::error::RtlsdrError::{
+    self, FrequencyOutOfRange, GainStageUnsupported, XtalCapUnsupported,
+};
+use crate::{GainStage, StageGain, TunerGain, TunerType, XtalCap, XtalCapValue};
+use std::ops::RangeInclusive;
 
-pub const KNOWN_TUNERS: [TunerInfo; 1] = [r820t::TUNER_INFO];
+// Probe order follows librtlsdr. The FC0012 shares its I2C address with the FC0013 and
+// only answers after a reset, so it comes last. The FC2580 also needs the reset.
+pub const KNOWN_TUNERS: [TunerInfo; 6] = [
+    e4k::TUNER_INFO,
+    fc001x::FC0013_TUNER_INFO,
+    r820t::TUNER_INFO,
+    r820t::R828D_TUNER_INFO,
+    fc2580::TUNER_INFO,
+    fc001x::FC0012_TUNER_INFO,
+];
+
+/// Gain steps in tenths of a dB for a tuner type, for when only the type is known,
+/// e.g. on the far side of an rtl_tcp connection. Empty for an unknown tuner.
+pub fn tuner_gains(tuner_type: TunerType) -> &'static [i32] {
+    match tuner_type {
+        TunerType::E4000 => &e4k::GAINS,
+        TunerType::FC0012 => &fc001x::FC0012_GAINS,
+        TunerType::FC0013 => &fc001x::FC0013_GAINS,
+        TunerType::FC2580 => &fc2580::GAINS,
+        TunerType::R820T | TunerType::R828D => &r820t::GAINS,
+        TunerType::Unknown => &[],
+    }
+}
+
+/// LO frequency of a retune in Hz, as requested and as produced after PLL quantisation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoFreq {
+    pub requested: u32,
+    pub actual: f64,
+}
 
 #[derive(Debug, Clone, Copy)]
 
@@ -13,17 +55,97 @@ pub struct TunerInfo {
     pub i2c_addr: u8,
     pub check_addr: u8,
     pub check_val: u8,
+    /// Bits of the check register compared against `check_val`
+    pub check_mask: u8,
+    /// Pulse the tuner reset on GPIO 5 before probing
+    pub probe_reset: bool,
     // pub gains: Vec<i8>,
 }
 
 pub trait Tuner: std::fmt::Debug {
     fn init(&mut self, handle: &Device) -> Result<()>;
     fn get_info(&self) -> Result<TunerInfo>;
+    fn tuner_type(&self) -> TunerType {
+        match self.get_info() {
+            Ok(info) => match info.id {
+                e4k::TUNER_ID => TunerType::E4000,
+                fc001x::FC0012_TUNER_ID => TunerType::FC0012,
+                fc001x::FC0013_TUNER_ID => TunerType::FC0013,
+                fc2580::TUNER_ID => TunerType::FC2580,
+                r820t::TUNER_ID => TunerType::R820T,
+                r820t::R828D_TUNER_ID => TunerType::R828D,
+                _ => TunerType::Unknown,
+            },
+            Err(_) => TunerType::Unknown,
+        }
+    }
     fn get_gains(&self) -> Result<Vec<i32>>;
+    #[allow(dead_code)]
     fn read_gain(&self, handle: &Device) -> Result<i32>;
     fn set_gain(&mut self, handle: &Device, gain: TunerGain) -> Result<()>;
+    /// Only some tuners have separately controllable IF gain stages
+    fn set_if_gain(&mut self, _handle: &Device, _stage: i32, _gain: i32) -> Result<()> {
+        Ok(())
+    }
+    /// Gain of each index of a stage, for tuners with separately controllable stages
+    fn get_stage_gains(&self, _stage: GainStage) -> Result<Vec<i32>> {
+        Ok(vec![])
+    }
+    fn set_stage_gain(
+        &mut self,
+        _handle: &Device,
+        stage: GainStage,
+        _gain: StageGain,
+    ) -> Result<()> {
+        Err(GainStageUnsupported {
+            stage,
+            tuner: self.tuner_type(),
+        })
+    }
     fn set_freq(&mut self, handle: &Device, freq: u32) -> Result<()>;
     fn set_bandwidth(&mut self, handle: &Device, bw: u32, rate: u32) -> Result<()>;
+    /// Bandwidths the IF filters can be set to, in Hz, ascending
+    fn get_bandwidths(&self) -> Vec<u32>;
+    /// Bandwidth the IF filters are currently set to, in Hz
+    fn get_bandwidth(&self) -> u32;
+    /// PLL lock state after the last retune, `None` for tuners that can't report it
+    fn pll_locked(&self) -> Option<bool> {
+        None
+    }
+    /// LO of the last retune, `None` before the first one
+    fn get_lo_freq(&self) -> Option<LoFreq> {
+        None
+    }
+    /// Result of the IF filter calibration, for tuners that run one
+    fn get_filter_cal_code(&self) -> Option<u8> {
+        None
+    }
+    /// Crystal load capacitor in use, for tuners that have a selectable one
+    fn get_xtal_cap(&self) -> Option<XtalCapValue> {
+        None
+    }
+    /// A fixed value applies on the next retune, calibration on the next init
+    fn set_xtal_cap(&mut self, _cap: XtalCap) -> Result<()> {
+        Err(XtalCapUnsupported {
+            tuner: self.tuner_type(),
+        })
+    }
+    /// Search for the crystal capacitor now, keeping the other settings. The result applies
+    /// on the next retune.
+    fn calibrate_xtal_cap(&mut self, _handle: &Device) -> Result<()> {
+        Err(XtalCapUnsupported {
+            tuner: self.tuner_type(),
+        })
+    }
+    /// Frequency ranges the tuner can tune to, in Hz
+    fn freq_ranges(&self) -> Vec<RangeInclusive<u64>>;
+    /// `FrequencyOutOfRange` for `freq`, listing what the tuner can tune to
+    fn out_of_range(&self, freq: u32) -> RtlsdrError {
+        FrequencyOutOfRange {
+            freq: freq.into(),
+            ranges: self.freq_ranges(),
+        }
+    }
     fn get_if_freq(&self) -> Result<u32>;
     fn get_xtal_freq(&self) -> Result<u32>;
     fn set_xtal_freq(&mut self, freq: u32) -> Result<()>;
@@ -42,6 +164,8 @@ impl Tuner for NoTuner {
             i2c_addr: 0,
             check_addr: 0,
             check_val: 0,
+            check_mask: 0,
+            probe_reset: false,
         })
     }
     fn get_gains(&self) -> Result<Vec<i32>> {
@@ -59,6 +183,15 @@ impl Tuner for NoTuner {
     fn set_bandwidth(&mut self, _handle: &Device, _bw: u32, _rate: u32) -> Result<()> {
         Ok(())
     }
+    fn get_bandwidths(&self) -> Vec<u32> {
+        vec![]
+    }
+    fn get_bandwidth(&self) -> u32 {
+        0
+    }
+    fn freq_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        vec![]
+    }
     fn get_xtal_freq(&self) -> Result<u32> {
         Ok(0)
     }
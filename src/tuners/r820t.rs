@@ -1,17 +1,32 @@
-use super::{Tuner, TunerGain, TunerInfo};
+use super::{LoFreq, Tuner, TunerGain, TunerInfo};
 use crate::device::Device;
 use crate::error::Result;
-use crate::error::RtlsdrError::RtlsdrErr;
-use log::info;
-
-const R820T_I2C_ADDR: u16 = 0x34;
-// const R828D_I2C_ADDR: u8 = 0x74; for now only support the T
+use crate::error::RtlsdrError::{InvalidGainIndex, XtalCalibrationFailed};
+use crate::{GainStage, StageGain, XtalCap, XtalCapValue};
+use log::{info, warn};
+use std::ops::RangeInclusive;
+use std::thread;
+use std::time::Duration;
+
+const R820T_I2C_ADDR: u8 = 0x34;
+const R828D_I2C_ADDR: u8 = 0x74;
+/// Crystal on most R828D dongles. The RTL-SDR Blog V4 runs its R828D from the 28.8 MHz RTL clock.
+pub const R828D_XTAL_FREQ: u32 = 16_000_000;
+/// The RTL-SDR Blog V4 upconverts HF by its 28.8 MHz clock
+const BLOG_V4_UPCONVERT_FREQ: u32 = 28_800_000;
+// Tuning range as limited by the PLL, and the Blog V4's lower limit through the upconverter
+const R82XX_FREQ_MIN: u32 = 24_000_000;
+const R82XX_FREQ_MAX: u32 = 1_766_000_000;
+const BLOG_V4_FREQ_MIN: u32 = 500_000;
 const VER_NUM: u8 = 49;
 pub const R82XX_IF_FREQ: u32 = 3570000;
 const NUM_REGS: usize = 35;
 const RW_REG_START: usize = 5; // registers 0-4 are read-only
 const NUM_CACHE_REGS: usize = NUM_REGS - RW_REG_START; // only cache RW regs
 const MAX_I2C_MSG_LEN: usize = 8;
+const FILTER_CAL_TRIES: usize = 2;
+/// Settling time of the PLL after changing the crystal capacitor
+const XTAL_CAP_SETTLE: Duration = Duration::from_millis(5);
 
 // Init registers (32 total, first 5 are read-only)
 const REG_INIT: [u8; NUM_CACHE_REGS] = [
@@ -29,12 +44,12 @@ const REG_INIT: [u8; NUM_CACHE_REGS] = [
 * input power, for raw results see:
 * http://steve-m.de/projects/rtl-sdr/gain_measurement/r820t/
 */
-const _VGA_BASE_GAIN: i32 = -47;
-const GAINS: [i32; 29] = [
+const VGA_BASE_GAIN: i32 = -47;
+pub const GAINS: [i32; 29] = [
     0, 9, 14, 27, 37, 77, 87, 125, 144, 157, 166, 197, 207, 229, 254, 280, 297, 328, 338, 364, 372,
     386, 402, 421, 434, 439, 445, 480, 496,
 ];
-const _R82XX_VGA_GAIN_STEPS: [i32; 16] = [
+const R82XX_VGA_GAIN_STEPS: [i32; 16] = [
     0, 26, 26, 30, 42, 35, 24, 13, 14, 32, 36, 34, 35, 37, 35, 36,
 ];
 
@@ -44,6 +59,94 @@ const R82XX_LNA_GAIN_STEPS: [i32; 16] =
 const R82XX_MIXER_GAIN_STEPS: [i32; 16] =
     [0, 5, 10, 10, 19, 9, 10, 25, 17, 10, 8, 16, 13, 6, 3, -8];
 
+/// IF filter registers and IF for a bandwidth (r82xx_set_bandwidth)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IfFilterSettings {
+    pub reg_0a: u8,
+    pub reg_0b: u8,
+    pub if_freq: u32,
+    /// Bandwidth the filters actually give
+    pub bw: u32,
+}
+
+const FILT_HP_BW1: i32 = 350_000;
+const FILT_HP_BW2: i32 = 380_000;
+const R82XX_IF_LOW_PASS_BW_TABLE: [i32; 10] = [
+    1_700_000, 1_600_000, 1_550_000, 1_450_000, 1_200_000, 900_000, 700_000, 550_000, 450_000,
+    350_000,
+];
+
+pub(crate) fn if_filter_settings(bw_in: u32) -> IfFilterSettings {
+    let mut bw: i32 = bw_in as i32;
+    let mut if_freq: u32;
+    let (reg_0a, reg_0b, real_bw): (u8, u8, u32) = if bw > 7_000_000 {
+        // BW: 8MHz
+        if_freq = 4_570_000;
+        (0x10, 0x0b, 8_000_000)
+    } else if bw > 6_000_000 {
+        // BW: 7MHz
+        if_freq = 4_570_000;
+        (0x10, 0x2a, 7_000_000)
+    } else if bw > R82XX_IF_LOW_PASS_BW_TABLE[0] + FILT_HP_BW1 + FILT_HP_BW2 {
+        // BW: 6MHz
+        if_freq = 3_570_000;
+        (0x10, 0x6b, 6_000_000)
+    } else {
+        if_freq = 2_300_000;
+        let (reg_0a, mut reg_0b): (u8, u8) = (0x00, 0x80);
+        let mut real_bw = 0;
+
+        if bw > R82XX_IF_LOW_PASS_BW_TABLE[0] + FILT_HP_BW1 {
+            bw -= FILT_HP_BW2;
+            if_freq += FILT_HP_BW2 as u32;
+            real_bw += FILT_HP_BW2;
+        } else {
+            reg_0b |= 0x20;
+        }
+
+        if bw > R82XX_IF_LOW_PASS_BW_TABLE[0] {
+            bw -= FILT_HP_BW1;
+            if_freq += FILT_HP_BW1 as u32;
+            real_bw += FILT_HP_BW1;
+        } else {
+            reg_0b |= 0x40;
+        }
+
+        // Find low-pass filter
+        // Want the element before the first that is lower than bw, or the last if none is
+        let lp_idx = R82XX_IF_LOW_PASS_BW_TABLE
+            .iter()
+            .position(|freq| bw > *freq)
+            .unwrap_or(R82XX_IF_LOW_PASS_BW_TABLE.len())
+            - 1;
+        reg_0b |= (15 - lp_idx) as u8;
+        real_bw += R82XX_IF_LOW_PASS_BW_TABLE[lp_idx];
+
+        if_freq -= (real_bw / 2) as u32;
+        (reg_0a, reg_0b, real_bw as u32)
+    };
+    IfFilterSettings {
+        reg_0a,
+        reg_0b,
+        if_freq,
+        bw: real_bw,
+    }
+}
+
+/// Bandwidths `if_filter_settings` can give, ascending
+fn if_filter_bandwidths() -> Vec<u32> {
+    let hp_corners = [0, FILT_HP_BW1, FILT_HP_BW2, FILT_HP_BW1 + FILT_HP_BW2];
+    let mut bandwidths: Vec<u32> = R82XX_IF_LOW_PASS_BW_TABLE
+        .iter()
+        .flat_map(|lp| hp_corners.iter().map(move |hp| (lp + hp) as u32))
+        .chain([6_000_000, 7_000_000, 8_000_000])
+        .map(|bw| if_filter_settings(bw).bw)
+        .collect();
+    bandwidths.sort_unstable();
+    bandwidths.dedup();
+    bandwidths
+}
+
 struct FreqRange {
     freq: u32,       // Start freq, in MHz
     open_d: u8,      // low
@@ -253,23 +356,13 @@ enum TunerType {
     DigitalTv,
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-enum XtalCapValue {
-    XtalLowCap30p,
-    XtalLowCap20p,
-    XtalLowCap10p,
-    XtalLowCap0p,
-    XtalHighCap0p,
-}
-
-#[allow(dead_code)]
-const XTAL_CAPACITOR_VALUES: [u8; 5] = [
-    0x0b, // XTAL_LOW_CAP_30P
-    0x02, // XTAL_LOW_CAP_20P
-    0x01, // XTAL_LOW_CAP_10P
-    0x00, // XTAL_LOW_CAP_0P
-    0x10, // XTAL_HIGH_CAP_0P
+// Register 0x10 value for each capacitor setting, in the order they're tried
+const XTAL_CAPACITOR_VALUES: [(u8, XtalCapValue); 5] = [
+    (0x0b, XtalCapValue::XtalLowCap30p),
+    (0x02, XtalCapValue::XtalLowCap20p),
+    (0x01, XtalCapValue::XtalLowCap10p),
+    (0x00, XtalCapValue::XtalLowCap0p),
+    (0x10, XtalCapValue::XtalHighCap0p),
 ];
 
 #[allow(dead_code)]
@@ -280,16 +373,46 @@ enum DeliverySystem {
     SysIsdbt,
 }
 
+/// Input selected on the RTL-SDR Blog V4, which has separate HF, VHF and UHF paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlogV4Band {
+    Hf,
+    Vhf,
+    Uhf,
+}
+
+impl BlogV4Band {
+    fn for_freq(freq: u32) -> BlogV4Band {
+        if freq <= BLOG_V4_UPCONVERT_FREQ {
+            BlogV4Band::Hf
+        } else if freq < 250_000_000 {
+            BlogV4Band::Vhf
+        } else {
+            BlogV4Band::Uhf
+        }
+    }
+}
+
+/// Driver for the Rafael Micro R82xx family, covering the R820T and the R828D
 #[derive(Debug)]
 pub struct R820T {
     pub info: TunerInfo,
+    is_r828d: bool,
+    blog_v4: bool,
+    // Last input selected, to avoid rewriting it on every retune
+    v4_band: Option<BlogV4Band>,
+    air_cable1_in: Option<u8>,
     regs: [u8; NUM_CACHE_REGS],
+    #[allow(dead_code)]
     pub freq: u32,
     int_freq: u32,
+    xtal_cap: XtalCap,
     xtal_cap_sel: XtalCapValue,
     xtal: u32,
     use_predetect: bool,
     has_lock: bool,
+    lo: Option<LoFreq>,
+    bandwidth: u32,
     fil_cal_code: u8,
     init_done: bool,
 }
@@ -299,25 +422,55 @@ pub const TUNER_ID: &str = "r820t";
 pub const TUNER_INFO: TunerInfo = TunerInfo {
     id: TUNER_ID,
     name: "Rafael Micro R820T",
-    i2c_addr: 0x34,
+    i2c_addr: R820T_I2C_ADDR,
     check_addr: 0x00,
     check_val: 0x69,
+    check_mask: 0xff,
+    probe_reset: false,
     // gains: vec![
     //     0, 9, 14, 27, 37, 77, 87, 125, 144, 157, 166, 197, 207, 229, 254, 280, 297, 328, 338, 364,
     //     372, 386, 402, 421, 434, 439, 445, 480, 496,
     // ],
 };
 
+pub const R828D_TUNER_ID: &str = "r828d";
+
+pub const R828D_TUNER_INFO: TunerInfo = TunerInfo {
+    id: R828D_TUNER_ID,
+    name: "Rafael Micro R828D",
+    i2c_addr: R828D_I2C_ADDR,
+    check_addr: 0x00,
+    check_val: 0x69,
+    check_mask: 0xff,
+    probe_reset: false,
+};
+
 impl R820T {
     pub fn new(_handle: &mut Device) -> R820T {
+        R820T::with_info(TUNER_INFO, false)
+    }
+
+    /// `blog_v4` enables the RTL-SDR Blog V4's HF upconverter and input switching
+    pub fn new_r828d(_handle: &mut Device, blog_v4: bool) -> R820T {
+        R820T::with_info(R828D_TUNER_INFO, blog_v4)
+    }
+
+    fn with_info(info: TunerInfo, blog_v4: bool) -> R820T {
         R820T {
-            info: TUNER_INFO,
+            info,
+            is_r828d: info.id == R828D_TUNER_ID,
+            blog_v4,
+            v4_band: None,
+            air_cable1_in: None,
             regs: REG_INIT,
             freq: 0,
             int_freq: 0,
-            xtal_cap_sel: XtalCapValue::XtalLowCap30p,
+            xtal_cap: XtalCap::Fixed(XtalCapValue::XtalHighCap0p),
+            xtal_cap_sel: XtalCapValue::XtalHighCap0p,
             xtal: 0,
             has_lock: false,
+            lo: None,
+            bandwidth: 0,
             init_done: false,
             use_predetect: false,
             fil_cal_code: 0,
@@ -328,11 +481,17 @@ impl R820T {
 impl Tuner for R820T {
     // Combined from r820t_init and r82xx_init
     fn init(&mut self, handle: &Device) -> Result<()> {
-        // TODO: set different I2C address and rafael_chip for R828D
         self.use_predetect = false;
+        // Inputs were reset along with the other registers
+        self.v4_band = None;
+        self.air_cable1_in = None;
 
-        // <original>TODO: R828D might need r82xx_xtal_check()
-        self.xtal_cap_sel = XtalCapValue::XtalHighCap0p;
+        let calibration = match self.xtal_cap {
+            XtalCap::Fixed(cap) => Ok(cap),
+            XtalCap::Calibrate => self.search_xtal_cap(handle),
+        };
+        // Finish initialising with the default if calibration fails, but still report it
+        self.xtal_cap_sel = *calibration.as_ref().unwrap_or(&XtalCapValue::XtalHighCap0p);
 
         // Initialize registers
         self.write_regs(handle, 0x05, &REG_INIT)?;
@@ -340,7 +499,7 @@ impl Tuner for R820T {
         self.set_tv_standard(handle, 3, TunerType::DigitalTv)?;
         self.sysfreq_sel(handle, 0, TunerType::DigitalTv, DeliverySystem::SysDvbt)?;
         self.init_done = true;
-        Ok(())
+        calibration.map(|_| ())
     }
 
     fn get_info(&self) -> Result<TunerInfo> {
@@ -407,81 +566,137 @@ impl Tuner for R820T {
         Ok(())
     }
 
+    fn get_stage_gains(&self, stage: GainStage) -> Result<Vec<i32>> {
+        let (steps, base) = match stage {
+            GainStage::Lna => (&R82XX_LNA_GAIN_STEPS, 0),
+            GainStage::Mixer => (&R82XX_MIXER_GAIN_STEPS, 0),
+            GainStage::Vga => (&R82XX_VGA_GAIN_STEPS, VGA_BASE_GAIN),
+        };
+        // The tables hold the increase from the previous index
+        Ok(steps
+            .iter()
+            .scan(base, |gain, step| {
+                *gain += step;
+                Some(*gain)
+            })
+            .collect())
+    }
+
+    fn set_stage_gain(&mut self, handle: &Device, stage: GainStage, gain: StageGain) -> Result<()> {
+        // Gain index register, and the value of its AGC bit (0x10) when the AGC is on
+        let (reg, agc_on) = match stage {
+            GainStage::Lna => (0x05, 0x00),
+            GainStage::Mixer => (0x07, 0x10),
+            // Auto means the VGA follows the demod's IF AGC pin
+            GainStage::Vga => (0x0c, 0x10),
+        };
+        match gain {
+            StageGain::Auto => self.write_reg_mask(handle, reg, agc_on, 0x10),
+            StageGain::Index(index) if index <= 0x0f => {
+                self.write_reg_mask(handle, reg, agc_on ^ 0x10, 0x10)?;
+                self.write_reg_mask(handle, reg, index, 0x0f)
+            }
+            StageGain::Index(index) => Err(InvalidGainIndex {
+                stage,
+                index,
+                supported: 0..=0x0f,
+            }),
+        }
+    }
+
     fn set_freq(&mut self, handle: &Device, freq: u32) -> Result<()> {
         info!("set_freq - freq: {}", freq);
-        let lo_freq = freq + self.int_freq;
+        // Tune HF on the Blog V4 through its upconverter, so applications don't need an offset
+        let rf_freq = if self.blog_v4 && freq < BLOG_V4_UPCONVERT_FREQ {
+            freq + BLOG_V4_UPCONVERT_FREQ
+        } else {
+            freq
+        };
+        let lo_freq = rf_freq + self.int_freq;
         info!("set_freq - lo_freq: {}", lo_freq);
         self.set_mux(handle, lo_freq)?;
         self.set_pll(handle, lo_freq)?;
+        if !self.has_lock {
+            return Ok(());
+        }
 
-        // TODO: Some extra stuff for the 828D tuner when we support that
+        if self.blog_v4 {
+            self.set_blog_v4_input(handle, freq)?;
+        } else if self.is_r828d {
+            // Switch between 'Cable1' and 'Air-In' inputs. We switch at 345 MHz, because
+            // that's where the noise floor has about the same level with identical LNA
+            // settings. The original driver used 320 MHz.
+            let air_cable1_in = if freq > 345_000_000 { 0x00 } else { 0x60 };
+            if self.air_cable1_in != Some(air_cable1_in) {
+                self.air_cable1_in = Some(air_cable1_in);
+                self.write_reg_mask(handle, 0x05, air_cable1_in, 0x60)?;
+            }
+        }
         Ok(())
     }
 
-    fn set_bandwidth(&mut self, handle: &Device, bw_in: u32, _rate: u32) -> Result<()> {
-        let mut bw: i32 = bw_in as i32;
-        const FILT_HP_BW1: i32 = 350_000;
-        const FILT_HP_BW2: i32 = 380_000;
-        const R82XX_IF_LOW_PASS_BW_TABLE: [i32; 10] = [
-            1_700_000, 1_600_000, 1_550_000, 1_450_000, 1_200_000, 900_000, 700_000, 550_000,
-            450_000, 350_000,
-        ];
-
-        let (reg_0a, reg_0b): (u8, u8) = if bw > 7_000_000 {
-            // BW: 8MHz
-            self.int_freq = 4_570_000;
-            (0x10, 0x0b)
-        } else if bw > 6_000_000 {
-            // BW: 7MHz
-            self.int_freq = 4_570_000;
-            (0x10, 0x2a)
-        } else if bw > R82XX_IF_LOW_PASS_BW_TABLE[0] + FILT_HP_BW1 + FILT_HP_BW2 {
-            // BW: 6MHz
-            self.int_freq = 3_570_000;
-            (0x10, 0x6b)
-        } else {
-            self.int_freq = 2_300_000;
-            let (reg_0a, mut reg_0b): (u8, u8) = (0x00, 0x80);
-            let mut real_bw = 0;
-
-            if bw > R82XX_IF_LOW_PASS_BW_TABLE[0] + FILT_HP_BW1 {
-                bw -= FILT_HP_BW2;
-                self.int_freq += FILT_HP_BW2 as u32;
-                real_bw += FILT_HP_BW2;
-            } else {
-                reg_0b |= 0x20;
-            }
+    fn set_bandwidth(&mut self, handle: &Device, bw: u32, _rate: u32) -> Result<()> {
+        let settings = if_filter_settings(bw);
+        self.int_freq = settings.if_freq;
+        self.bandwidth = settings.bw;
+        let (reg_0a, reg_0b) = (settings.reg_0a, settings.reg_0b);
 
-            if bw > R82XX_IF_LOW_PASS_BW_TABLE[0] {
-                bw -= FILT_HP_BW1;
-                self.int_freq += FILT_HP_BW1 as u32;
-                real_bw += FILT_HP_BW1;
-            } else {
-                reg_0b |= 0x40;
-            }
+        // Keep the calibration code next to the filter Q bit
+        self.write_reg_mask(handle, 0x0a, reg_0a | self.fil_cal_code, 0x1f)?;
+        self.write_reg_mask(handle, 0x0b, reg_0b, 0xef)?;
+        Ok(())
+    }
 
-            // Find low-pass filter
-            let mut lp_idx = 0;
-            // Want the element before the first that is lower than bw
-            for (i, freq) in R82XX_IF_LOW_PASS_BW_TABLE.iter().enumerate() {
-                lp_idx = i;
-                if bw > *freq {
-                    break;
-                }
-            }
-            lp_idx -= 1;
-            reg_0b |= (15 - lp_idx) as u8;
-            real_bw += R82XX_IF_LOW_PASS_BW_TABLE[lp_idx];
+    fn get_bandwidths(&self) -> Vec<u32> {
+        if_filter_bandwidths()
+    }
 
-            self.int_freq -= (real_bw / 2) as u32;
-            (reg_0a, reg_0b)
-        };
+    fn get_bandwidth(&self) -> u32 {
+        self.bandwidth
+    }
 
-        self.write_reg_mask(handle, 0x0a, reg_0a, 0x10)?;
-        self.write_reg_mask(handle, 0x0b, reg_0b, 0xef)?;
+    fn pll_locked(&self) -> Option<bool> {
+        Some(self.has_lock)
+    }
+
+    fn get_lo_freq(&self) -> Option<LoFreq> {
+        self.lo
+    }
+
+    fn get_xtal_cap(&self) -> Option<XtalCapValue> {
+        Some(self.xtal_cap_sel)
+    }
+
+    fn get_filter_cal_code(&self) -> Option<u8> {
+        Some(self.fil_cal_code)
+    }
+
+    fn set_xtal_cap(&mut self, cap: XtalCap) -> Result<()> {
+        self.xtal_cap = cap;
+        if let XtalCap::Fixed(cap) = cap {
+            self.xtal_cap_sel = cap;
+        }
         Ok(())
     }
 
+    fn calibrate_xtal_cap(&mut self, handle: &Device) -> Result<()> {
+        // The checks start from the power-on registers, put the current settings back after
+        let regs = self.regs;
+        let calibration = self.search_xtal_cap(handle);
+        self.write_regs(handle, 0x05, &regs)?;
+        self.xtal_cap_sel = *calibration.as_ref().unwrap_or(&XtalCapValue::XtalHighCap0p);
+        calibration.map(|_| ())
+    }
+
+    fn freq_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        let min = if self.blog_v4 {
+            BLOG_V4_FREQ_MIN
+        } else {
+            R82XX_FREQ_MIN
+        };
+        vec![min.into()..=R82XX_FREQ_MAX.into()]
+    }
+
     fn get_if_freq(&self) -> Result<u32> {
         Ok(self.int_freq)
     }
@@ -518,6 +733,37 @@ impl Tuner for R820T {
 impl R820T {
     // Tuning logic
 
+    /// Set the notch filters and select the HF, VHF or UHF input of the RTL-SDR Blog V4
+    fn set_blog_v4_input(&mut self, handle: &Device, freq: u32) -> Result<()> {
+        // Notch filters are off when tuned within their band and on outside it
+        let in_notch_band = freq <= 2_200_000
+            || (85_000_000..=112_000_000).contains(&freq)
+            || (172_000_000..=242_000_000).contains(&freq);
+        let open_d = if in_notch_band { 0x00 } else { 0x08 };
+        self.write_reg_mask(handle, 0x17, open_d, 0x08)?;
+
+        let band = BlogV4Band::for_freq(freq);
+        if self.v4_band == Some(band) {
+            return Ok(());
+        }
+        self.v4_band = Some(band);
+        info!("[R828D] Blog V4 input: {:?}", band);
+
+        // Cable 2 is the HF input
+        let cable_2_in = if band == BlogV4Band::Hf { 0x08 } else { 0x00 };
+        self.write_reg_mask(handle, 0x06, cable_2_in, 0x08)?;
+        // Newer batches switch the upconverter through GPIO 5
+        handle.set_gpio_output(5)?;
+        handle.set_gpio_bit(5, band != BlogV4Band::Hf)?;
+        // Cable 1 is the VHF input
+        let cable_1_in = if band == BlogV4Band::Vhf { 0x40 } else { 0x00 };
+        self.write_reg_mask(handle, 0x05, cable_1_in, 0x40)?;
+        // Air-In is the UHF input
+        let air_in = if band == BlogV4Band::Uhf { 0x00 } else { 0x20 };
+        self.write_reg_mask(handle, 0x05, air_in, 0x20)?;
+        Ok(())
+    }
+
     fn set_mux(&mut self, handle: &Device, freq: u32) -> Result<()> {
         // Get the proper frequency range
         let freq_mhz = freq / 1_000_000;
@@ -598,8 +844,7 @@ impl R820T {
 
         let mut data: [u8; 5] = [0; 5];
         self.read_reg(handle, 0x00, &mut data, 5)?;
-        // TODO: if chip is R828D set vco_power_ref = 1
-        let vco_power_ref = 2;
+        let vco_power_ref = if self.is_r828d { 1 } else { 2 };
         let vco_fine_tune = (data[4] & 0x30) >> 4;
         match vco_fine_tune.cmp(&vco_power_ref) {
             std::cmp::Ordering::Greater => div_num -= 1,
@@ -616,9 +861,7 @@ impl R820T {
         let mut vco_fra = ((vco_freq - 2 * pll_ref as u64 * nint as u64) / 1000) as u32;
 
         if nint > ((128 / vco_power_ref) - 1) {
-            return Err(RtlsdrErr(format!(
-                "[R82xx] No valid PLL values for {freq} Hz!"
-            )));
+            return Err(self.out_of_range(freq));
         }
         // Nint = 4 * Ni2c + Si2c + 13
         // Some weird wrap-around stuff here, example cases from original code:
@@ -656,6 +899,11 @@ impl R820T {
         }
         self.write_regs(handle, 0x16, &[(sdm >> 8) as u8])?;
         self.write_regs(handle, 0x15, &[(sdm & 0xff) as u8])?;
+        let vco_actual = 2.0 * pll_ref as f64 * (nint as f64 + sdm as f64 / 65536.0);
+        self.lo = Some(LoFreq {
+            requested: freq,
+            actual: vco_actual / (2u32 << div_num) as f64,
+        });
         for i in 0..2 {
             // Check if PLL has locked
             self.read_reg(handle, 0x00, &mut data, 3)?;
@@ -667,11 +915,11 @@ impl R820T {
                 #[cfg(feature = "rtl_sdr_blog")]
                 self.write_reg_mask(handle, 0x12, 0x06, 0xff)?;
                 #[cfg(not(feature = "rtl_sdr_blog"))]
-                self.write_reg_mask(handle, 0x12, 0x80, 0xe0)?;
+                self.write_reg_mask(handle, 0x12, 0x60, 0xe0)?;
             }
         }
         if (data[2] & 0x40) == 0 {
-            info!("[R82xx] PLL not locked!");
+            warn!("[R82xx] PLL not locked at {} Hz!", freq);
             self.has_lock = false;
             return Ok(());
         }
@@ -777,10 +1025,11 @@ impl R820T {
         self.write_reg_mask(handle, 0x11, cp_cur, 0x38)?;
 
         // RTLSDRBLOG. Improve L-band performance by setting PLL drop out to 2.0v
-        #[cfg(feature = "rtl_sdr_blog")]
-        {
-            div_buf_cur = 0xa0;
-        }
+        let div_buf_cur = if cfg!(feature = "rtl_sdr_blog") {
+            0xa0
+        } else {
+            div_buf_cur
+        };
 
         self.write_reg_mask(handle, 0x17, div_buf_cur, 0x30)?;
         self.write_reg_mask(handle, 0x0a, filter_cur, 0x60)?;
@@ -830,6 +1079,44 @@ impl R820T {
         Ok(())
     }
 
+    /// Run the IF filter calibration, caching the code for later bandwidth settings
+    fn calibrate_filter(&mut self, handle: &Device, hp_cor: u8, cal_lo: u32) -> Result<()> {
+        for _ in 0..FILTER_CAL_TRIES {
+            // Set filt_cap
+            self.write_reg_mask(handle, 0x0b, hp_cor, 0x60)?;
+            // set cali clk = on
+            self.write_reg_mask(handle, 0x0f, 0x04, 0x04)?;
+            // X'tal cap 0pF for PLL
+            self.write_reg_mask(handle, 0x10, 0x00, 0x03)?;
+
+            self.set_pll(handle, cal_lo)?;
+
+            // Start trigger
+            self.write_reg_mask(handle, 0x0b, 0x10, 0x10)?;
+            // Stop trigger
+            self.write_reg_mask(handle, 0x0b, 0x00, 0x10)?;
+            self.write_reg_mask(handle, 0x0f, 0x00, 0x04)?;
+
+            // Check if calibration worked
+            let mut data: [u8; 5] = [0; 5];
+            self.read_reg(handle, 0x00, &mut data, 5)?;
+            self.fil_cal_code = data[4] & 0x0f;
+            if self.fil_cal_code != 0x0f {
+                break;
+            }
+        }
+        if self.fil_cal_code == 0x0f {
+            // Narrowest
+            warn!("[R82xx] Filter calibration failed, using the narrowest setting");
+            self.fil_cal_code = 0;
+        } else {
+            info!("[R82xx] Filter calibration code: {}", self.fil_cal_code);
+        }
+        // The calibration LO isn't a retune
+        self.lo = None;
+        Ok(())
+    }
+
     fn set_tv_standard(&mut self, handle: &Device, _bw: u32, tuner_type: TunerType) -> Result<()> {
         /* BW < 6 MHz */
         let if_khz = 3570;
@@ -858,40 +1145,12 @@ impl R820T {
             self.write_reg_mask(handle, 0x1d, 0x00, 0x38)?;
         }
         self.int_freq = if_khz * 1000;
+        // The filter Q and HP corner below are the 6 MHz setting
+        self.bandwidth = 6_000_000;
 
         /* Check if standard changed. If so, filter calibration is needed */
         /* Since we call this function only once in rtlsdr, force calibration */
-        let need_calibration = true;
-        if need_calibration {
-            for _ in 0..2 {
-                // Set filt_cap
-                self.write_reg_mask(handle, 0x0b, hp_cor, 0x60)?;
-                // set cali clk = on
-                self.write_reg_mask(handle, 0x0f, 0x04, 0x04)?;
-                // X'tal cap 0pF for PLL
-                self.write_reg_mask(handle, 0x10, 0x00, 0x03)?;
-
-                self.set_pll(handle, filt_cal_lo * 1000)?;
-
-                // Start trigger
-                self.write_reg_mask(handle, 0x0b, 0x10, 0x10)?;
-                // Stop trigger
-                self.write_reg_mask(handle, 0x0b, 0x00, 0x10)?;
-                self.write_reg_mask(handle, 0x0f, 0x00, 0x04)?;
-
-                // Check if calibration worked
-                let mut data: [u8; 5] = [0; 5];
-                self.read_reg(handle, 0x00, &mut data, 5)?;
-                self.fil_cal_code = data[4] & 0x0f;
-                if self.fil_cal_code != 0x0f {
-                    break;
-                }
-            }
-            // Narrowest
-            if self.fil_cal_code == 0x0f {
-                self.fil_cal_code = 0;
-            }
-        }
+        self.calibrate_filter(handle, hp_cor, filt_cal_lo * 1000)?;
         self.write_reg_mask(handle, 0x0a, filt_q | self.fil_cal_code, 0x1f)?;
 
         // Set BW, Filter_gain, and HP corner
@@ -922,18 +1181,22 @@ impl R820T {
         Ok(())
     }
 
-    fn _xtal_check(&mut self, handle: &Device) -> Result<u8> {
+    /// Keep the smallest capacitance of three checks, like the Linux driver
+    fn search_xtal_cap(&mut self, handle: &Device) -> Result<XtalCapValue> {
+        self.write_regs(handle, 0x05, &REG_INIT)?;
+        let mut cap = self.xtal_check(handle)?;
+        for _ in 1..3 {
+            cap = cap.max(self.xtal_check(handle)?);
+        }
+        info!("[R82xx] Xtal capacitor calibrated to {:?}", cap);
+        Ok(cap)
+    }
+
+    fn xtal_check(&mut self, handle: &Device) -> Result<XtalCapValue> {
         let mut data: [u8; 3] = [0; 3];
 
         // Initialize register cache
-        for (i, r) in REG_INIT
-            .iter()
-            .enumerate()
-            .take(NUM_REGS)
-            .skip(RW_REG_START)
-        {
-            self.regs[i] = *r;
-        }
+        self.regs = REG_INIT;
 
         // cap 30pF & Drive Low
         self.write_reg_mask(handle, 0x10, 0x0b, 0x0b)?;
@@ -945,8 +1208,9 @@ impl R820T {
         self.write_reg_mask(handle, 0x13, 0x00, 0x40)?;
 
         // Try several xtal capacitor alternatives
-        for cap_val in XTAL_CAPACITOR_VALUES.iter() {
-            self.write_reg_mask(handle, 0x10, *cap_val, 0x1b)?;
+        for (cap_val, cap) in XTAL_CAPACITOR_VALUES {
+            self.write_reg_mask(handle, 0x10, cap_val, 0x1b)?;
+            thread::sleep(XTAL_CAP_SETTLE);
             self.read_reg(handle, 0x00, &mut data, 3)?;
             if data[2] & 0x40 == 0 {
                 continue;
@@ -954,12 +1218,10 @@ impl R820T {
 
             let val = data[2] & 0x3f;
             if (self.xtal == 16_000_000 && !(23..=29).contains(&val)) || val != 0x3f {
-                return Ok(*cap_val);
+                return Ok(cap);
             }
         }
-        Err(RtlsdrErr(
-            "Unable to find good xtal capacitor value!".to_string(),
-        ))
+        Err(XtalCalibrationFailed)
     }
 
     /// Write register with bit-masked data
@@ -973,8 +1235,8 @@ impl R820T {
 
     /// Read register data from local cache
     /// # Panics
-    ///     * reg < RW_REG_START
-    ///     * reg > NUM_REGS
+    /// * reg < RW_REG_START
+    /// * reg > NUM_REGS
     fn read_cache_reg(&self, reg: usize) -> u8 {
         assert!(reg >= RW_REG_START); // is assert the best thing to use here?
         let index = reg - RW_REG_START;
@@ -1001,7 +1263,7 @@ impl R820T {
             let mut buf: Vec<u8> = vec![0; size + 1];
             buf[0] = reg_index as u8;
             buf[1..].copy_from_slice(&val[val_index..val_index + size]);
-            handle.i2c_write(R820T_I2C_ADDR, &buf)?;
+            handle.i2c_write(self.info.i2c_addr.into(), &buf)?;
             val_index += size;
             reg_index += size;
             len -= size;
@@ -1015,8 +1277,8 @@ impl R820T {
     // (r82xx_read)
     fn read_reg(&self, handle: &Device, reg: usize, buf: &mut [u8], len: u8) -> Result<()> {
         assert!(buf.len() >= len as usize);
-        handle.i2c_write(R820T_I2C_ADDR, &[reg as u8])?;
-        handle.i2c_read(R820T_I2C_ADDR, buf, len)?;
+        handle.i2c_write(self.info.i2c_addr.into(), &[reg as u8])?;
+        handle.i2c_read(self.info.i2c_addr.into(), buf, len)?;
         // Need to reverse each byte...for some reason?
         for b in buf {
             *b = bit_reverse(*b);
@@ -0,0 +1,503 @@
+//! Software emulation of an RTL2832U with an R820T, R828D or E4000 tuner, for running the
+//! driver without hardware.
+//!
+//! Only the behaviour the driver depends on is emulated: register storage for the
+//! demod, USB and SYS blocks, the I2C repeater gate, the R820T register file including
+//! bit-reversed reads and PLL lock status, the E4000 PLL, and the EEPROM. The bulk endpoint produces
+//! synthetic IQ samples.
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::device::{Transport, BLOCK_DEMOD, BLOCK_IIC, BLOCK_SYS, BLOCK_USB, EEPROM_ADDR, GPO};
+use crate::eeprom::{EepromConfig, EEPROM_SIZE};
+use crate::error::Result;
+
+const TUNER_NUM_REGS: usize = 32;
+// The E4000 has registers up to 0xa0
+const TUNER_REG_SPACE: usize = 256;
+const DEF_XTAL_FREQ: f64 = 28_800_000.0;
+// VCO range the emulated PLL locks over
+const VCO_MIN: f64 = 1_750_000_000.0;
+const VCO_MAX: f64 = 3_600_000_000.0;
+// Status register contents (before bit reversal on the wire)
+const TUNER_CHIP_ID: u8 = 0x96;
+const PLL_LOCKED: u8 = 0x40;
+const VCO_BAND: u8 = 0x2a;
+const FIL_CAL_CODE: u8 = 0x08;
+const E4K_CHIP_ID: u8 = 0x40;
+const E4K_PLL_LOCKED: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalSource {
+    /// Complex tone at an absolute RF frequency in Hz, with amplitude relative to full scale
+    Tone { freq: f64, amplitude: f64 },
+    /// White noise with the given RMS amplitude relative to full scale
+    Noise { amplitude: f64 },
+    /// Incrementing byte counter, the same as the RTL2832 test mode
+    Counter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtualTuner {
+    R820T,
+    R828D,
+    /// Zero-IF tuner
+    E4000,
+}
+
+impl VirtualTuner {
+    fn i2c_addr(&self) -> u16 {
+        match self {
+            VirtualTuner::R820T => 0x34,
+            VirtualTuner::R828D => 0x74,
+            VirtualTuner::E4000 => 0xc8,
+        }
+    }
+
+    /// VCO fine tune readback at the nominal VCO current, which the driver compares
+    /// against when picking the mixer divider
+    fn vco_fine_tune(&self) -> u8 {
+        match self {
+            VirtualTuner::R820T => 0x20,
+            VirtualTuner::R828D => 0x10,
+            VirtualTuner::E4000 => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualConfig {
+    pub tuner: VirtualTuner,
+    /// Signals summed into the IQ stream. Test mode always produces the counter.
+    pub sources: Vec<SignalSource>,
+    /// Initial EEPROM contents
+    pub eeprom: [u8; EEPROM_SIZE],
+    /// Crystal frequency the emulated tuner PLL runs from
+    pub tuner_xtal: f64,
+    /// Never report PLL lock, to exercise tuning failures
+    pub force_pll_unlock: bool,
+    /// Crystal capacitor settings, as register 0x10 & 0x1b, that report PLL lock whatever the
+    /// dividers, so the crystal check has one to find
+    pub xtal_lock_caps: Vec<u8>,
+    /// Deliver samples no faster than the programmed sample rate
+    pub realtime: bool,
+}
+
+impl Default for VirtualConfig {
+    fn default() -> Self {
+        let mut eeprom = [0xff; EEPROM_SIZE];
+        EepromConfig::default()
+            .write_to(&mut eeprom)
+            .expect("default EEPROM config fits");
+        VirtualConfig {
+            tuner: VirtualTuner::R820T,
+            sources: vec![SignalSource::Noise { amplitude: 0.05 }],
+            eeprom,
+            tuner_xtal: DEF_XTAL_FREQ,
+            force_pll_unlock: false,
+            xtal_lock_caps: Vec::new(),
+            realtime: false,
+        }
+    }
+}
+
+/// Emulated device. Clones share state, so a clone kept aside can inspect registers
+/// after the original has been handed to `RtlSdr`.
+#[derive(Debug, Clone)]
+pub struct VirtualDevice {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    config: VirtualConfig,
+    // USB and SYS block registers, keyed by (block, addr)
+    regs: HashMap<(u16, u16), u8>,
+    // Demod registers, keyed by (page, addr)
+    demod: HashMap<(u16, u16), u8>,
+    tuner: [u8; TUNER_REG_SPACE],
+    tuner_ptr: usize,
+    eeprom: [u8; EEPROM_SIZE],
+    eeprom_ptr: usize,
+    // Signal generator
+    phases: Vec<f64>,
+    counter: u8,
+    noise_seed: u64,
+    started: Option<Instant>,
+    samples_sent: u64,
+}
+
+impl VirtualDevice {
+    pub fn new(config: VirtualConfig) -> VirtualDevice {
+        let mut tuner = [0; TUNER_REG_SPACE];
+        tuner[0] = TUNER_CHIP_ID;
+        VirtualDevice {
+            state: Arc::new(Mutex::new(State {
+                eeprom: config.eeprom,
+                phases: vec![0.0; config.sources.len()],
+                config,
+                regs: HashMap::new(),
+                demod: HashMap::new(),
+                tuner,
+                tuner_ptr: 0,
+                eeprom_ptr: 0,
+                counter: 0,
+                noise_seed: 0x2545_f491_4f6c_dd1d,
+                started: None,
+                samples_sent: 0,
+            })),
+        }
+    }
+
+    pub fn eeprom(&self) -> [u8; EEPROM_SIZE] {
+        self.state.lock().eeprom
+    }
+
+    pub fn demod_reg(&self, page: u16, addr: u16) -> u8 {
+        self.state.lock().demod_reg(page, addr)
+    }
+
+    pub fn tuner_reg(&self, reg: usize) -> u8 {
+        self.state.lock().tuner[reg]
+    }
+
+    /// Current output level of a GPIO pin
+    pub fn gpio(&self, pin: u8) -> bool {
+        let state = self.state.lock();
+        state.regs.get(&(BLOCK_SYS, GPO)).unwrap_or(&0) & (1 << pin) != 0
+    }
+
+    /// Replace the signal sources while streaming
+    pub fn set_sources(&self, sources: Vec<SignalSource>) {
+        let mut state = self.state.lock();
+        state.phases = vec![0.0; sources.len()];
+        state.config.sources = sources;
+    }
+
+    /// Current sample rate as programmed into the demod resampler
+    pub fn sample_rate(&self) -> f64 {
+        self.state.lock().sample_rate()
+    }
+
+    /// Tuner LO frequency as programmed into its PLL
+    pub fn lo_freq(&self) -> f64 {
+        self.state.lock().lo_freq()
+    }
+
+    /// IF the demod DDC mixes down to 0 Hz
+    pub fn if_freq(&self) -> f64 {
+        self.state.lock().if_freq()
+    }
+
+    /// RF frequency that ends up at 0 Hz in the IQ stream, from the tuner PLL and demod IF
+    pub fn tuned_freq(&self) -> f64 {
+        self.state.lock().tuned_freq()
+    }
+}
+
+impl State {
+    fn demod_reg(&self, page: u16, addr: u16) -> u8 {
+        *self.demod.get(&(page, addr)).unwrap_or(&0)
+    }
+
+    fn i2c_repeater(&self) -> bool {
+        self.demod_reg(1, 0x01) & 0x08 != 0
+    }
+
+    fn test_mode(&self) -> bool {
+        self.demod_reg(0, 0x19) == 0x03
+    }
+
+    fn sample_rate(&self) -> f64 {
+        let ratio = ((self.demod_reg(1, 0x9f) as u32) << 24)
+            | ((self.demod_reg(1, 0xa0) as u32) << 16)
+            | ((self.demod_reg(1, 0xa1) as u32) << 8)
+            | self.demod_reg(1, 0xa2) as u32;
+        let ratio = ratio | ((ratio & 0x0800_0000) << 1);
+        if ratio == 0 {
+            return 0.0;
+        }
+        DEF_XTAL_FREQ * (1u64 << 22) as f64 / ratio as f64
+    }
+
+    /// IF programmed into the demod DDC
+    fn if_freq(&self) -> f64 {
+        let reg = ((self.demod_reg(1, 0x19) as u32 & 0x3f) << 16)
+            | ((self.demod_reg(1, 0x1a) as u32) << 8)
+            | self.demod_reg(1, 0x1b) as u32;
+        // 22 bit two's complement
+        let reg = ((reg << 10) as i32) >> 10;
+        -(reg as f64) * DEF_XTAL_FREQ / (1u64 << 22) as f64
+    }
+
+    fn vco_freq(&self) -> f64 {
+        let ni = (self.tuner[0x14] & 0x3f) as u32;
+        let si = (self.tuner[0x14] >> 6) as u32;
+        let nint = 4 * ni + si + 13;
+        let sdm = ((self.tuner[0x16] as u32) << 8) | self.tuner[0x15] as u32;
+        2.0 * self.config.tuner_xtal * (nint as f64 + sdm as f64 / 65536.0)
+    }
+
+    fn lo_freq(&self) -> f64 {
+        if self.config.tuner == VirtualTuner::E4000 {
+            return self.e4k_lo_freq();
+        }
+        let div_num = (self.tuner[0x10] >> 5) & 0x07;
+        self.vco_freq() / (2u32 << div_num) as f64
+    }
+
+    /// E4000 LO from the Z and X multipliers and the SYNTH7 output divider
+    fn e4k_lo_freq(&self) -> f64 {
+        let z = self.tuner[0x09] as f64;
+        let x = u16::from_le_bytes([self.tuner[0x0a], self.tuner[0x0b]]) as f64;
+        // Bit 3 selects 3-phase mixing, which has its own set of dividers
+        let r = match self.tuner[0x0d] & 0x0f {
+            0x0f => 48,
+            0x0e => 40,
+            0x0d => 32,
+            0x0c => 24,
+            0x0b => 16,
+            0x0a => 12,
+            0x09 | 0x03 => 8,
+            0x02 => 6,
+            0x01 => 4,
+            _ => 2,
+        };
+        self.config.tuner_xtal * (z + x / 65536.0) / r as f64
+    }
+
+    fn tuned_freq(&self) -> f64 {
+        match self.config.tuner {
+            // The DDC shifts the zero-IF output up by its IF
+            VirtualTuner::E4000 => self.lo_freq() + self.if_freq(),
+            // The R82xx mixes the RF below its LO down to the IF
+            VirtualTuner::R820T | VirtualTuner::R828D => self.lo_freq() - self.if_freq(),
+        }
+    }
+
+    fn pll_locked(&self) -> bool {
+        if self.config.tuner == VirtualTuner::E4000 {
+            return !self.config.force_pll_unlock;
+        }
+        let vco = self.vco_freq();
+        let cap = self.tuner[0x10] & 0x1b;
+        !self.config.force_pll_unlock
+            && ((VCO_MIN..=VCO_MAX).contains(&vco) || self.config.xtal_lock_caps.contains(&cap))
+    }
+
+    /// Tuner status registers are read-only and derived from the current setup
+    fn tuner_read(&self, reg: usize) -> u8 {
+        if self.config.tuner == VirtualTuner::E4000 {
+            return match reg {
+                0x02 => E4K_CHIP_ID,
+                0x07 if self.pll_locked() => self.tuner[reg] | E4K_PLL_LOCKED,
+                0x07 => self.tuner[reg] & !E4K_PLL_LOCKED,
+                _ => self.tuner[reg],
+            };
+        }
+        match reg {
+            0x02 if self.pll_locked() => PLL_LOCKED | VCO_BAND,
+            0x02 => VCO_BAND,
+            0x04 => self.config.tuner.vco_fine_tune() | FIL_CAL_CODE,
+            _ => self.tuner[reg],
+        }
+    }
+
+    fn write_i2c(&mut self, addr: u16, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match addr {
+            EEPROM_ADDR => {
+                self.eeprom_ptr = buf[0] as usize;
+                for b in &buf[1..] {
+                    self.eeprom[self.eeprom_ptr % EEPROM_SIZE] = *b;
+                    self.eeprom_ptr += 1;
+                }
+            }
+            addr if addr == self.config.tuner.i2c_addr() && self.i2c_repeater() => {
+                self.tuner_ptr = buf[0] as usize;
+                for b in &buf[1..] {
+                    if self.tuner_ptr < TUNER_REG_SPACE {
+                        self.tuner[self.tuner_ptr] = *b;
+                    }
+                    self.tuner_ptr += 1;
+                }
+            }
+            // Nothing acknowledges this address
+            _ => return Err(rusb::Error::Pipe.into()),
+        }
+        Ok(buf.len())
+    }
+
+    fn read_i2c(&mut self, addr: u16, buf: &mut [u8]) -> Result<usize> {
+        match addr {
+            EEPROM_ADDR => {
+                for b in buf.iter_mut() {
+                    *b = self.eeprom[self.eeprom_ptr % EEPROM_SIZE];
+                    self.eeprom_ptr += 1;
+                }
+            }
+            // The E4000 reads from the register last written
+            addr if addr == self.config.tuner.i2c_addr()
+                && self.i2c_repeater()
+                && self.config.tuner == VirtualTuner::E4000 =>
+            {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = self.tuner_read((self.tuner_ptr + i) % TUNER_REG_SPACE);
+                }
+            }
+            // The R820T always reads from register 0 and sends each byte bit-reversed
+            addr if addr == self.config.tuner.i2c_addr() && self.i2c_repeater() => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = bit_reverse(self.tuner_read(i % TUNER_NUM_REGS));
+                }
+            }
+            _ => return Err(rusb::Error::Pipe.into()),
+        }
+        Ok(buf.len())
+    }
+
+    fn next_noise(&mut self) -> f64 {
+        // Sum of uniforms is close enough to Gaussian for test signals
+        let mut sum = 0.0;
+        for _ in 0..4 {
+            self.noise_seed ^= self.noise_seed << 13;
+            self.noise_seed ^= self.noise_seed >> 7;
+            self.noise_seed ^= self.noise_seed << 17;
+            sum += (self.noise_seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5;
+        }
+        sum * 3f64.sqrt()
+    }
+
+    fn fill_samples(&mut self, buf: &mut [u8]) {
+        if self.test_mode() || self.config.sources.contains(&SignalSource::Counter) {
+            for b in buf.iter_mut() {
+                *b = self.counter;
+                self.counter = self.counter.wrapping_add(1);
+            }
+            return;
+        }
+        let rate = self.sample_rate();
+        let tuned = self.tuned_freq();
+        let sources = self.config.sources.clone();
+        for iq in buf.chunks_exact_mut(2) {
+            let (mut i, mut q) = (0.0, 0.0);
+            for (n, source) in sources.iter().enumerate() {
+                match source {
+                    SignalSource::Tone { freq, amplitude } => {
+                        i += amplitude * self.phases[n].cos();
+                        q += amplitude * self.phases[n].sin();
+                        if rate > 0.0 {
+                            let step = 2.0 * PI * (freq - tuned) / rate;
+                            self.phases[n] = (self.phases[n] + step) % (2.0 * PI);
+                        }
+                    }
+                    SignalSource::Noise { amplitude } => {
+                        i += amplitude * self.next_noise();
+                        q += amplitude * self.next_noise();
+                    }
+                    SignalSource::Counter => (),
+                }
+            }
+            iq[0] = (127.5 + 127.5 * i).round().clamp(0.0, 255.0) as u8;
+            iq[1] = (127.5 + 127.5 * q).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+impl Transport for VirtualDevice {
+    fn claim_interface(&mut self, _iface: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_control(
+        &self,
+        _request_type: u8,
+        _request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        let mut state = self.state.lock();
+        match index >> 8 {
+            BLOCK_DEMOD => {
+                let (page, addr) = (index & 0xff, value >> 8);
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = state.demod_reg(page, addr + i as u16);
+                }
+                Ok(buf.len())
+            }
+            BLOCK_IIC => state.read_i2c(value, buf),
+            block => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = *state.regs.get(&(block, value + i as u16)).unwrap_or(&0);
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn write_control(
+        &self,
+        _request_type: u8,
+        _request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        let mut state = self.state.lock();
+        match index >> 8 {
+            BLOCK_DEMOD => {
+                let (page, addr) = (index & 0x0f, value >> 8);
+                for (i, b) in buf.iter().enumerate() {
+                    state.demod.insert((page, addr + i as u16), *b);
+                }
+                Ok(buf.len())
+            }
+            BLOCK_IIC => state.write_i2c(value, buf),
+            block @ (BLOCK_USB | BLOCK_SYS) => {
+                for (i, b) in buf.iter().enumerate() {
+                    state.regs.insert((block, value + i as u16), *b);
+                }
+                Ok(buf.len())
+            }
+            _ => Err(rusb::Error::Pipe.into()),
+        }
+    }
+
+    fn read_bulk(&self, _endpoint: u8, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        let wait = {
+            let mut state = self.state.lock();
+            state.fill_samples(buf);
+            let rate = state.sample_rate();
+            if state.config.realtime && rate > 0.0 {
+                let started = *state.started.get_or_insert_with(Instant::now);
+                state.samples_sent += (buf.len() / 2) as u64;
+                let due = Duration::from_secs_f64(state.samples_sent as f64 / rate);
+                due.checked_sub(started.elapsed())
+            } else {
+                None
+            }
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+        Ok(buf.len())
+    }
+}
+
+fn bit_reverse(byte: u8) -> u8 {
+    byte.reverse_bits()
+}
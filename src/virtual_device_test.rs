@@ -0,0 +1,64 @@
+use crate::virtual_device::{SignalSource, VirtualConfig, VirtualDevice};
+use crate::{DeviceSelector, RtlSdr};
+
+#[test]
+fn test_open_virtual() {
+    let sdr = RtlSdr::open(DeviceSelector::Virtual(Box::default())).unwrap();
+    sdr.set_sample_rate(2_048_000).unwrap();
+    sdr.set_center_freq(100_000_000).unwrap();
+    assert_eq!(sdr.get_center_freq(), 100_000_000);
+    assert_eq!(sdr.get_eeprom_config().unwrap().product, "RTL2838UHIDIR");
+}
+
+#[test]
+fn test_tuning_and_rate() {
+    let dev = VirtualDevice::new(VirtualConfig::default());
+    let sdr = RtlSdr::open_transport(Box::new(dev.clone())).unwrap();
+    sdr.set_sample_rate(2_400_000).unwrap();
+    sdr.set_center_freq(433_920_000).unwrap();
+    assert!((dev.sample_rate() - 2_400_000.0).abs() < 1.0);
+    assert!((dev.tuned_freq() - 433_920_000.0).abs() < 1000.0);
+    // I2C repeater is closed again after tuning
+    assert_eq!(dev.demod_reg(1, 0x01) & 0x08, 0);
+}
+
+#[test]
+fn test_testmode_counter() {
+    let sdr = RtlSdr::open(DeviceSelector::Virtual(Box::default())).unwrap();
+    sdr.set_testmode(true).unwrap();
+    sdr.reset_buffer().unwrap();
+    let mut buf = [0; 1024];
+    sdr.read_sync(&mut buf).unwrap();
+    for pair in buf.windows(2) {
+        assert_eq!(pair[1], pair[0].wrapping_add(1));
+    }
+}
+
+#[test]
+fn test_tone() {
+    let config = VirtualConfig {
+        sources: vec![SignalSource::Tone {
+            freq: 100_250_000.0,
+            amplitude: 0.5,
+        }],
+        ..Default::default()
+    };
+    let sdr = RtlSdr::open(DeviceSelector::Virtual(Box::new(config))).unwrap();
+    sdr.set_sample_rate(1_000_000).unwrap();
+    sdr.set_center_freq(100_000_000).unwrap();
+    let mut buf = [0; 8192];
+    sdr.read_sync(&mut buf).unwrap();
+    // Tone should be 250 kHz above center, a quarter turn per sample
+    let iq: Vec<(f64, f64)> = buf
+        .chunks_exact(2)
+        .map(|s| (s[0] as f64 - 127.5, s[1] as f64 - 127.5))
+        .collect();
+    let mut turn = 0.0;
+    for w in iq.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        // Angle of b * conj(a)
+        turn += (b.1 * a.0 - b.0 * a.1).atan2(b.0 * a.0 + b.1 * a.1);
+    }
+    let freq = turn / (iq.len() - 1) as f64 / (2.0 * std::f64::consts::PI) * 1_000_000.0;
+    assert!((freq - 250_000.0).abs() < 2_000.0, "tone at {freq} Hz");
+}